@@ -98,9 +98,9 @@ fn snake_segment_path(canvas_ctx: &CanvasRenderingContext2d, x: f64, y: f64, w:
     }
 }
 
-fn pid_to_color(pid: PlayerId) -> &'static str {
+fn palette_color(i: usize) -> &'static str {
     // TODO: more than 8 unique colors, but still distinguishable
-    match pid.0 % 8 {
+    match i % 8 {
         0 => "#ff0000",
         1 => "#00ff00",
         2 => "#ffff00",
@@ -112,10 +112,10 @@ fn pid_to_color(pid: PlayerId) -> &'static str {
     }
 }
 
-fn render_tile(canvas_ctx: &CanvasRenderingContext2d, x: f64, y: f64, w: f64, h: f64, board: &Board, coord: Coord) {
+fn render_tile(canvas_ctx: &CanvasRenderingContext2d, x: f64, y: f64, w: f64, h: f64, tile: &Tile) {
     use Tile::*;
     // TODO: cache colors
-    match board[coord] {
+    match tile {
         Empty => {
             canvas_ctx.set_fill_style(&JsValue::from_str(&"#f0f0f0"));
             canvas_ctx.fill_rect(x, y, w, h);
@@ -128,48 +128,113 @@ fn render_tile(canvas_ctx: &CanvasRenderingContext2d, x: f64, y: f64, w: f64, h:
             canvas_ctx.set_fill_style(&JsValue::from_str(&"#f0f0f0"));
             canvas_ctx.fill_rect(x, y, w, h);
         },
-        Food => {
+        Food(kind) => {
             canvas_ctx.set_fill_style(&JsValue::from_str(&"#f0f0f0"));
             canvas_ctx.fill_rect(x, y, w, h);
-            canvas_ctx.set_fill_style(&JsValue::from_str(&"#808000"));
+            canvas_ctx.set_fill_style(&JsValue::from_str(match kind {
+                FoodKind::Normal => "#808000",
+                FoodKind::Golden => "#ffd700",
+                FoodKind::Rotten => "#604020",
+            }));
             canvas_ctx.begin_path();
             canvas_ctx.ellipse(x+w/2.0, y+h/2.0, w/4.0, h/4.0, 0.0, 0.0, TAU).unwrap();
             canvas_ctx.fill();
         },
+        PowerUp(kind) => {
+            canvas_ctx.set_fill_style(&JsValue::from_str(&"#f0f0f0"));
+            canvas_ctx.fill_rect(x, y, w, h);
+            let r = |_, _| (w/3.0, h/3.0);
+            let (c_x, c_y) = (x+w/2.0, y+h/2.0);
+            match kind {
+                PowerUpKind::SpeedBoost => {
+                    canvas_ctx.set_fill_style(&JsValue::from_str(&"#ff8000"));
+                    regular_polygon_path(canvas_ctx, 3, c_x, c_y, &r, 0.0);
+                },
+                PowerUpKind::Ghost => {
+                    canvas_ctx.set_fill_style(&JsValue::from_str(&"#c0c0ff"));
+                    canvas_ctx.begin_path();
+                    canvas_ctx.ellipse(c_x, c_y, w/3.0, h/3.0, 0.0, 0.0, TAU).unwrap();
+                },
+                PowerUpKind::ShrinkOthers => {
+                    canvas_ctx.set_fill_style(&JsValue::from_str(&"#800080"));
+                    regular_polygon_path(canvas_ctx, 4, c_x, c_y, &r, 0.0);
+                },
+                PowerUpKind::Shield => {
+                    canvas_ctx.set_fill_style(&JsValue::from_str(&"#0080ff"));
+                    regular_polygon_path(canvas_ctx, 6, c_x, c_y, &r, 0.0);
+                },
+            }
+            canvas_ctx.fill();
+            canvas_ctx.set_stroke_style(&JsValue::from_str(&"#101010"));
+            canvas_ctx.stroke();
+        },
     }
 }
 
-fn render_board(canvas: &HtmlCanvasElement, canvas_ctx: &CanvasRenderingContext2d, board: &Board, player_segments: &BTreeMap<PlayerId, VecDeque<Coord>>) {
+fn render_board(canvas: &HtmlCanvasElement, canvas_ctx: &CanvasRenderingContext2d, board: &Board, player_segments: &BTreeMap<PlayerId, VecDeque<Coord>>, teams: &BTreeMap<PlayerId, TeamId>) {
     let xscale = canvas.width() as f64 / board.width as f64;
     let yscale = canvas.height() as f64 / board.height as f64;
 
-    for y in 0..board.height {
-        for x in 0..board.width {
-            render_tile(canvas_ctx, (x as f64)*xscale, (y as f64)*yscale, xscale, yscale, board, coord(x, y));
-        }
+    for (c, tile) in board.cells() {
+        let point = c.to_vec2() * Vec2::new(xscale, yscale);
+        render_tile(canvas_ctx, point.x, point.y, xscale, yscale, tile);
     }
-    let extract_dir = |coord: &Coord| if let Tile::WormSegment { dir, .. } = board[*coord] { Some(dir) } else { None };
     for (pid, segments) in player_segments.iter() {
-        canvas_ctx.begin_path();
-        let mut points = Vec::new();
+        // teammates share a color
+        let color = palette_color(teams.get(pid).map_or(pid.0, |team| team.0));
+        // split the snake wherever it wraps around an edge of the board, so that each piece is drawn as a contiguous path
+        let mut run = Vec::new();
         for i in 0..segments.len() {
-            let prev_dir = segments.get(i-1).and_then(extract_dir);
-            let dir = extract_dir(&segments[i]).unwrap();
-            let next_dir = if i < segments.len() - 1 { Some(dir) } else { None };
-            let point = segments[i].to_vec2() * Vec2::new(xscale, yscale);
-            points.push((prev_dir, dir, next_dir, point));
-            snake_segment_path(canvas_ctx, point.x, point.y, xscale, yscale, prev_dir, dir, next_dir, true);
-        }
-        points.reverse();
-        for (prev_dir, dir, next_dir, point) in points.iter() {
-            snake_segment_path(canvas_ctx, point.x, point.y, xscale, yscale, *prev_dir, *dir, *next_dir, false);
+            if i > 0 && board.crosses_edge(segments[i-1], segments[i]) {
+                render_snake_run(canvas_ctx, xscale, yscale, board, color, &run, false);
+                run.clear();
+            }
+            run.push(segments[i]);
         }
-        canvas_ctx.close_path();
-        canvas_ctx.set_fill_style(&JsValue::from_str(pid_to_color(*pid)));
-        canvas_ctx.fill();
-        canvas_ctx.set_stroke_style(&JsValue::from_str(&"#101010"));
-        canvas_ctx.stroke();
+        render_snake_run(canvas_ctx, xscale, yscale, board, color, &run, true);
+    }
+}
+
+fn render_phase(canvas: &HtmlCanvasElement, canvas_ctx: &CanvasRenderingContext2d, state: &SnakeGameState, our_pid: Option<PlayerId>) {
+    let text = match state.phase {
+        RoundPhase::Running(_) if our_pid.is_some_and(|pid| state.can_respawn(pid)) => "Press space to respawn".into(),
+        RoundPhase::WaitingForPlayers => format!("Waiting for players ({}/{})", state.players.len(), state.config.rounds.map_or(0, |rules| rules.min_players)),
+        RoundPhase::Countdown(start) => format!("Starting in {}", start.saturating_sub(state.tick)),
+        RoundPhase::Running(_) => return,
+        RoundPhase::RoundOver { winner: Some(Winner::Player(pid)), .. } => format!("Player {} wins!", pid.0),
+        RoundPhase::RoundOver { winner: Some(Winner::Team(team)), .. } => format!("Team {} wins!", team.0),
+        RoundPhase::RoundOver { winner: None, .. } => "Draw".into(),
+    };
+    canvas_ctx.set_font("32px sans-serif");
+    canvas_ctx.set_text_align("center");
+    canvas_ctx.set_fill_style(&JsValue::from_str(&"#101010"));
+    canvas_ctx.fill_text(&text, canvas.width() as f64 / 2.0, canvas.height() as f64 / 2.0).unwrap();
+}
+
+fn render_snake_run(canvas_ctx: &CanvasRenderingContext2d, xscale: f64, yscale: f64, board: &Board, color: &str, segments: &[Coord], has_head: bool) {
+    let extract_dir = |coord: &Coord| if let Some(Tile::WormSegment { dir, .. }) = board.get(*coord) { Some(*dir) } else { None };
+    canvas_ctx.begin_path();
+    let mut points = Vec::new();
+    for i in 0..segments.len() {
+        let prev_dir = if i > 0 { extract_dir(&segments[i-1]) } else { None };
+        let dir = match extract_dir(&segments[i]) {
+            Some(dir) => dir,
+            None => { log(&format!("no snake segment at {:?}", segments[i])); return },
+        };
+        let next_dir = if i < segments.len() - 1 || !has_head { Some(dir) } else { None };
+        let point = segments[i].to_vec2() * Vec2::new(xscale, yscale);
+        points.push((prev_dir, dir, next_dir, point));
+        snake_segment_path(canvas_ctx, point.x, point.y, xscale, yscale, prev_dir, dir, next_dir, true);
     }
+    points.reverse();
+    for (prev_dir, dir, next_dir, point) in points.iter() {
+        snake_segment_path(canvas_ctx, point.x, point.y, xscale, yscale, *prev_dir, *dir, *next_dir, false);
+    }
+    canvas_ctx.close_path();
+    canvas_ctx.set_fill_style(&JsValue::from_str(color));
+    canvas_ctx.fill();
+    canvas_ctx.set_stroke_style(&JsValue::from_str(&"#101010"));
+    canvas_ctx.stroke();
 }
 
 fn keyevent_to_playerinput(e: &KeyboardEvent) -> Option<SnakePlayerInput> {
@@ -180,10 +245,15 @@ fn keyevent_to_playerinput(e: &KeyboardEvent) -> Option<SnakePlayerInput> {
         KeyEvent::DOM_VK_DOWN | KeyEvent::DOM_VK_S => Some(ChangeDirection(Down)),
         KeyEvent::DOM_VK_LEFT | KeyEvent::DOM_VK_A => Some(ChangeDirection(Left)),
         KeyEvent::DOM_VK_RIGHT | KeyEvent::DOM_VK_D => Some(ChangeDirection(Right)),
+        KeyEvent::DOM_VK_SPACE | KeyEvent::DOM_VK_RETURN => Some(Respawn),
+        KeyEvent::DOM_VK_SHIFT => Some(SetBoost(true)),
         _ => None,
     }
 }
 
+/// How long the connection has to be down before we try to open a new one
+const RECONNECT_DELAY_MS: f64 = 1000.0;
+
 enum PlayerInputDelta {
     Started(SnakePlayerInput),
     Ended(SnakePlayerInput),
@@ -217,7 +287,7 @@ pub fn wasm_main() -> Result<JsValue, JsValue> {
                 let reader: FileReader = e.target().unwrap().dyn_into().unwrap();
                 let buffer: ArrayBuffer = reader.result().unwrap().dyn_into().unwrap();
                 let bytes = Uint8Array::new(&buffer);
-                let msg = bincode::deserialize::<ServerToClient>(&bytes.to_vec());
+                let msg = bincode::deserialize::<ServerToClient<SnakeGameState>>(&bytes.to_vec());
                 log(&format!("{:?}", msg));
                 if let Ok(msg) = msg {
                     let _ = s2c_tx.send(msg);
@@ -233,8 +303,19 @@ pub fn wasm_main() -> Result<JsValue, JsValue> {
     }) as Box<dyn FnMut(MessageEvent)>);
 
     let host = document.location().and_then(|loc| loc.host().ok()).unwrap();
-    let ws = web_sys::WebSocket::new(&format!("ws://{}/client_connection", host)).unwrap();
-    ws.set_onmessage(onmessage_closure.as_ref().dyn_ref());
+    // `http://host/#name` joins the room called `name`, and `http://host/?spectate#name` watches it
+    let room = document.location().and_then(|loc| loc.hash().ok()).map(|hash| hash.trim_start_matches('#').to_string()).unwrap_or_default();
+    let spectate = document.location().and_then(|loc| loc.search().ok()).is_some_and(|search| search.trim_start_matches('?').split('&').any(|arg| arg == "spectate"));
+    let endpoint = if spectate { "spectate" } else { "client_connection" };
+    let url = if room.is_empty() {
+        format!("ws://{}/{}", host, endpoint)
+    } else {
+        format!("ws://{}/{}/{}", host, endpoint, room)
+    };
+    let mut ws = web_sys::WebSocket::new(&url).unwrap();
+    // kept around to hook up to the new websocket whenever we reconnect
+    let onmessage: js_sys::Function = onmessage_closure.as_ref().unchecked_ref::<js_sys::Function>().clone();
+    ws.set_onmessage(Some(&onmessage));
 
     onmessage_closure.forget();
 
@@ -245,36 +326,70 @@ pub fn wasm_main() -> Result<JsValue, JsValue> {
 
 
     // TODO: populate from websocket
-    let mut our_pid = PlayerId(0);
-    let mut gamestate = SnakeGameState::new();
+    // `None` while spectating
+    let mut our_pid = None;
+    let mut gamestate = SnakeGameState::new(SnakeGameConfig::default());
+    // don't keep asking for a resync while waiting for one
+    let mut awaiting_resync = false;
+    let mut ticks_per_second = 0;
+    // what we reconnect with to get our snake back, and when the connection was last seen to be down
+    let mut session: Option<SessionToken> = None;
+    let mut closed_since = None;
 
-    let mut current_inputs: BTreeMap<PlayerId, <SnakeGameState as GameState>::PlayerInput> = BTreeMap::new();
     let mut last_ts = None;
     let (input_tx, input_rx) = mpsc::channel();
     let raf_closure = Closure::wrap(Box::new(move |ts: f64| {
         if let Some(ts2) = last_ts.as_mut() {
             let seconds_since_last = (ts - *ts2)/1000.0;
-            let num_ticks = (seconds_since_last*TICKS_PER_SECOND) as usize;
+            let num_ticks = (seconds_since_last * ticks_per_second as f64) as usize;
+            if ws.ready_state() != web_sys::WebSocket::CLOSED {
+                closed_since = None;
+            } else if ts - *closed_since.get_or_insert(ts) >= RECONNECT_DELAY_MS {
+                log("reconnecting");
+                ws = web_sys::WebSocket::new(&match session {
+                    Some(session) => format!("{}?session={}", url, session.0),
+                    None => url.clone(),
+                }).unwrap();
+                ws.set_onmessage(Some(&onmessage));
+                closed_since = None;
+            }
             while let Ok(msg) = s2c_rx.try_recv() {
                 use ServerToClient::*;
                 match msg {
-                    Initialize { pid, world } => { our_pid = pid; gamestate = world; },
-                    DoTick { tick, inputs } => { gamestate.tick(&inputs); },
-                    PlayerDisconnected { pid } => { gamestate.remove_player(pid, 0); },
+                    Initialize { pid, world, ticks_per_second: rate, session: token } => match world.validate() {
+                        Ok(()) => { our_pid = pid; gamestate = world; ticks_per_second = rate; session = token; awaiting_resync = false; },
+                        Err(e) => log(&format!("rejecting malformed world: {}", e)),
+                    },
+                    DoTick { tick, inputs, checksum } => {
+                        let desynced = tick != gamestate.tick || checksum.is_some_and(|checksum| checksum != gamestate.checksum());
+                        if desynced && !awaiting_resync {
+                            log(&format!("desynced at tick {} (ours is {}), requesting a resync", tick, gamestate.tick));
+                            // this fails while we're reconnecting, but reconnecting resyncs us anyway
+                            let _ = ws.send_with_u8_array(&bincode::serialize(&ClientToServer::<SnakeGameState>::RequestResync).unwrap());
+                            awaiting_resync = true;
+                        }
+                        gamestate.tick(&inputs);
+                    },
+                    PlayerJoined { pid } => { gamestate.player_joined(pid); },
+                    PlayerDisconnected { pid } => { gamestate.player_left(pid); },
+                    PlayerDetached { pid } => { gamestate.player_detached(pid); },
+                    PlayerReattached { pid } => { gamestate.player_reattached(pid); },
                 }
             }
             while let Ok(input) = input_rx.try_recv() {
-                match input {
-                    PlayerInputDelta::Started(input) => { current_inputs.insert(our_pid, input); },
-                    PlayerInputDelta::Ended(input) => if current_inputs[&our_pid] == input { current_inputs.remove(&our_pid); },
+                // the game state remembers headings and boosting, so each input only needs to be sent once
+                let input = match input {
+                    PlayerInputDelta::Started(input) => input,
+                    PlayerInputDelta::Ended(SnakePlayerInput::SetBoost(true)) => SnakePlayerInput::SetBoost(false),
+                    PlayerInputDelta::Ended(_) => continue,
+                };
+                if our_pid.is_none() || ws.ready_state() != web_sys::WebSocket::OPEN {
+                    continue
                 }
-            }
-            if let Some(input) = current_inputs.get(&our_pid) {
-                ws.send_with_u8_array(&bincode::serialize(&ClientToServer::InputAtTick { tick: gamestate.tick, input: *input }).unwrap()).unwrap();
+                ws.send_with_u8_array(&bincode::serialize(&ClientToServer::<SnakeGameState>::InputAtTick { tick: gamestate.tick, input }).unwrap()).unwrap();
             }
             if num_ticks > 0 {
                 log(&format!("{:?} {:?}", seconds_since_last, num_ticks));
-                log(&format!("current_inputs: {:?}", current_inputs));
                 /*for _ in 0..num_ticks {
                     let events = gamestate.tick(&current_inputs);
                     log(&format!("events: {:?}", events));
@@ -284,7 +399,8 @@ pub fn wasm_main() -> Result<JsValue, JsValue> {
         } else {
             last_ts = Some(ts);
         }
-        render_board(&canvas, &canvas_ctx, &gamestate.board, &gamestate.player_segments);
+        render_board(&canvas, &canvas_ctx, &gamestate.board, &gamestate.player_segments, &gamestate.teams);
+        render_phase(&canvas, &canvas_ctx, &gamestate, our_pid);
     }) as Box<dyn FnMut(f64)>);
     let raf_closure_jsval = raf_closure.as_ref().clone();
     raf_closure.forget();
@@ -292,15 +408,18 @@ pub fn wasm_main() -> Result<JsValue, JsValue> {
     let input_tx_ = input_tx.clone();
     let keyup_closure = Closure::wrap(Box::new(move |e: KeyboardEvent| {
         log(&format!("keyup {:?}", e));
-        /*if let Some(x) = keyevent_to_playerinput(&e) {
+        if let Some(x) = keyevent_to_playerinput(&e) {
             input_tx_.send(PlayerInputDelta::Ended(x)).unwrap();
-        }*/
+        }
     }) as Box<dyn FnMut(_)>);
     window.add_event_listener_with_callback("keyup", keyup_closure.as_ref().dyn_ref().unwrap()).unwrap();
     keyup_closure.forget();
 
     let keydown_closure = Closure::wrap(Box::new(move |e: KeyboardEvent| {
         log(&format!("keydown {:?}", e));
+        if e.repeat() {
+            return;
+        }
         if let Some(x) = keyevent_to_playerinput(&e) {
             input_tx.send(PlayerInputDelta::Started(x)).unwrap();
         }
@@ -1,45 +1,89 @@
 use rand::{RngCore, SeedableRng};
-use std::collections::{BTreeMap, VecDeque};
-use std::{cmp::{PartialOrd, Ord}, fmt::Debug};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::{cmp::{PartialOrd, Ord}, fmt::{self, Debug}};
 use std::ops::{Add, Index, IndexMut, Mul, Neg, Sub};
 use serde::{Serialize, Deserialize};
 
+// Nothing reachable from `GameState::tick` may use floating point: the server runs natively and the clients run as
+// wasm32, and they need to agree bit-for-bit on every tick. Floats are only for rendering.
+
 pub const TAU: f64 = 2.0 * std::f64::consts::PI;
-pub const TICKS_PER_SECOND: f64 = 2.0;
 
-pub trait GameState {
+pub trait GameState: Serialize+for<'de>Deserialize<'de>+Clone+Debug {
     type PlayerInput: Serialize+for<'de>Deserialize<'de>+Copy+Clone+Debug+PartialEq+Eq+PartialOrd+Ord;
     type GameEvent: Serialize+for<'de>Deserialize<'de>+Copy+Clone+Debug+PartialEq+Eq+PartialOrd+Ord;
-    type S2CMsg: Serialize+for<'de>Deserialize<'de>+Clone+Debug;
-    type C2SMsg: Serialize+for<'de>Deserialize<'de>+Clone+Debug;
+    type Config: Serialize+for<'de>Deserialize<'de>+Clone+Debug;
 
-    fn new() -> Self;
-    fn tick(&mut self, inputs: &BTreeMap<PlayerId, Self::PlayerInput>) -> Vec<Self::GameEvent>;
+    fn new(config: Self::Config) -> Self;
+    /// Called between ticks, in the same order on the server and on every client
+    fn player_joined(&mut self, pid: PlayerId);
+    /// Called between ticks, in the same order on the server and on every client
+    fn player_left(&mut self, pid: PlayerId);
+    /// Called between ticks when `pid`'s connection drops, but they might still come back for their `PlayerId`
+    fn player_detached(&mut self, _pid: PlayerId) {}
+    /// Called between ticks when a detached player comes back
+    fn player_reattached(&mut self, _pid: PlayerId) {}
+    /// Each player's inputs since the last tick, in the order they were made
+    fn tick(&mut self, inputs: &BTreeMap<PlayerId, Vec<Self::PlayerInput>>) -> Vec<Self::GameEvent>;
+    /// The number of the next tick to be simulated
+    fn current_tick(&self) -> u64;
+    /// A hash of everything that `tick` depends on, which must come out the same on every platform
+    fn checksum(&self) -> u64 {
+        fnv1a64(&bincode::serialize(self).expect("game states are always serializable"))
+    }
+    /// The world to send a newly connected client
+    fn snapshot(&self) -> Self {
+        self.clone()
+    }
 }
 
 /* ===== Message types ===== */
 
 #[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum SnakePlayerInput {
+    /// Queued up, so that several quick turns between ticks each take effect on a tick of their own
     ChangeDirection(Direction),
+    /// Come back with a new snake after dying, once the respawn cooldown is over
+    Respawn,
+    /// Start or stop moving two tiles per tick, at the cost of some length
+    SetBoost(bool),
 }
 
 #[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum SnakeGameEvent {
-    PlayerDied(PlayerId, u32),
-    PlayerAteFood(PlayerId, Coord),
+    /// The `PlayerId` of the killer, if any, comes last
+    PlayerDied(PlayerId, DeathCause, Option<PlayerId>),
+    PlayerAteFood(PlayerId, FoodKind, Coord),
+    FoodExpired(FoodKind, Coord),
+    /// The arena's outermost open ring will turn into walls at the given tick
+    ArenaShrinking(u64),
+    RoundPhaseChanged(RoundPhase),
+    PlayerGotPowerUp(PlayerId, PowerUpKind, Coord),
+    PlayerRespawned(PlayerId),
+    ScoreChanged(PlayerId, PlayerScore),
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
-pub enum ServerToClient {
-    Initialize { pid: PlayerId, world: SnakeGameState },
-    DoTick { tick: u64, inputs: BTreeMap<PlayerId, SnakePlayerInput> },
-    PlayerDisconnected { pid: PlayerId }
+#[serde(bound = "")]
+pub enum ServerToClient<G: GameState> {
+    /// The server runs `ticks_per_second` ticks a second; spectators have no `pid` and no `session`, which players can
+    /// reconnect with to get their `pid` back
+    Initialize { pid: Option<PlayerId>, world: G, ticks_per_second: u32, session: Option<SessionToken> },
+    /// `checksum` is sent every so often, and is the server's `GameState::checksum` as of `tick`, before `inputs`
+    DoTick { tick: u64, inputs: BTreeMap<PlayerId, Vec<G::PlayerInput>>, checksum: Option<u64> },
+    PlayerJoined { pid: PlayerId },
+    PlayerDisconnected { pid: PlayerId },
+    /// The player's connection dropped, and the server is holding their place for a while
+    PlayerDetached { pid: PlayerId },
+    PlayerReattached { pid: PlayerId },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
-pub enum ClientToServer {
-    InputAtTick { tick: u64, input: SnakePlayerInput },
+#[serde(bound = "")]
+pub enum ClientToServer<G: GameState> {
+    InputAtTick { tick: u64, input: G::PlayerInput },
+    /// Asks for a fresh `Initialize` after the client's world stopped matching the server's
+    RequestResync,
 }
 
 /* ===== Data structures ===== */
@@ -47,31 +91,226 @@ pub enum ClientToServer {
 #[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct PlayerId(pub usize);
 
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TeamId(pub usize);
+
+/// A secret that lets a player who lost their connection reclaim their `PlayerId`, so it's left out of debug output
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SessionToken(pub u64);
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DetachedSnakes {
+    /// A disconnected player's snake carries on straight ahead
+    KeepMoving,
+    /// A disconnected player's snake stays where it is until they come back
+    Freeze,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FriendlyFire {
+    /// Teammates pass through each other
+    PassThrough,
+    /// Running into a teammate is as fatal as running into anyone else
+    Collide,
+}
+
 #[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Coord { x: isize, y: isize }
 
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DeathCause {
+    Wall,
+    OwnBody,
+    OtherBody,
+    /// Two or more heads moved onto the same tile, or through each other
+    HeadOn,
+    /// The snake was caught in a ring of the arena as it closed
+    ArenaClosed,
+    Disconnect,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PlayerScore {
+    pub food_eaten: u64,
+    pub length: u64,
+    pub peak_length: u64,
+    pub kills: u64,
+}
+
 #[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
 pub struct Vec2 { pub x: f64, pub y: f64 }
 
 #[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Direction { Up, Down, Left, Right }
 
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PowerUpKind {
+    /// Move two tiles per tick
+    SpeedBoost,
+    /// Pass through other snakes, and be passed through by them
+    Ghost,
+    /// Immediately cut every other snake's tail short
+    ShrinkOthers,
+    /// Survive running into other snakes, who still die running into you
+    Shield,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FoodKind {
+    Normal,
+    /// Worth several segments
+    Golden,
+    /// Takes segments away
+    Rotten,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct FoodSpec {
+    /// How often this kind spawns, relative to the other kinds
+    pub weight: u32,
+    /// How many segments eating it adds, or takes away if negative
+    pub growth: i64,
+    /// How many ticks it stays on the board uneaten, if it ever goes away
+    pub lifetime: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RoundPhase {
+    /// Waiting for enough players to join to start a round
+    WaitingForPlayers,
+    /// Snakes are on a fresh board but can't move until the given tick
+    Countdown(u64),
+    /// The round started at the given tick
+    Running(u64),
+    /// The next round's countdown starts at `restart_at`
+    RoundOver { winner: Option<Winner>, restart_at: u64 },
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Winner {
+    Player(PlayerId),
+    /// With teams, the team of the longest snakes wins, however many of its snakes tie for it
+    Team(TeamId),
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct RoundRules {
+    /// A round ends when at most one snake (or team) is left, or none if this is 1
+    pub min_players: usize,
+    pub countdown_ticks: u64,
+    /// A round that runs this long is won by the longest surviving snake, or its team
+    pub time_limit: Option<u64>,
+    /// How long the results are shown before the next round
+    pub round_over_ticks: u64,
+}
+
+/// A battle-royale schedule on which the arena's open area closes in one ring at a time
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ArenaShrink {
+    /// The tick of the first shrink, counted from the start of the round
+    pub start: u64,
+    /// Ticks between shrinks
+    pub interval: u64,
+    /// How many ticks before each shrink `SnakeGameEvent::ArenaShrinking` is sent
+    pub warning: u64,
+    /// The arena stops shrinking before its open area gets narrower or shorter than this
+    pub min_size: usize,
+}
+
 #[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Tile {
     Empty,
     Wall,
     WormSegment { pid: PlayerId, dir: Direction, },
-    Food,
+    Food(FoodKind),
+    PowerUp(PowerUpKind),
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum BoardError {
+    SizeMismatch { width: usize, height: usize, tiles: usize },
+    /// A board has to have at least one tile to put anything on
+    TooSmall { width: usize, height: usize },
+    OutOfBounds(Coord),
+    MalformedSnake(PlayerId),
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Topology {
+    /// The board is surrounded by walls
+    Walled,
+    /// Leaving one edge of the board enters it from the opposite edge
+    Toroidal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Board {
     pub width: usize,
     pub height: usize,
     pub tiles: Vec<Tile>,
+    pub topology: Topology,
+    /// If there are any, new snakes' heads only spawn on these
+    pub spawn_points: Vec<Coord>,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SnakeGameConfig {
+    pub width: usize,
+    pub height: usize,
+    pub seed: u64,
+    /// The board is topped up to `food_per_player * num_snakes + food_base` foods every tick, so spectators and dead
+    /// players don't count
+    pub food_per_player: u64,
+    pub food_base: u64,
+    pub normal_food: FoodSpec,
+    pub golden_food: FoodSpec,
+    pub rotten_food: FoodSpec,
+    pub starting_length: usize,
+    /// How many ticks a new snake must be able to go straight without danger
+    pub spawn_lookahead: usize,
+    /// How many ticks a new snake passes through, and is passed through by, other snakes
+    pub spawn_protection_ticks: u64,
+    pub topology: Topology,
+    /// How many power-ups are kept on the board
+    pub powerup_count: u64,
+    /// How many ticks a power-up's effect lasts
+    pub powerup_duration: u64,
+    /// How many segments `PowerUpKind::ShrinkOthers` takes off of every other snake
+    pub shrink_others_amount: usize,
+    /// An arena to play on instead of an empty `width` by `height` board with the given `topology`
+    pub map: Option<Board>,
+    pub arena_shrink: Option<ArenaShrink>,
+    /// How many teams players are split into; 0 is free-for-all
+    pub team_count: usize,
+    pub friendly_fire: FriendlyFire,
+    /// Without rounds, the game is one round that never ends, and players join it as soon as they connect
+    pub rounds: Option<RoundRules>,
+    /// How many ticks after dying a player can respawn, if they can at all; nobody respawns with `rounds`
+    pub respawn_cooldown: Option<u64>,
+    /// A boosting snake drops a tail segment as food once every this many ticks
+    pub boost_cost_interval: u64,
+    /// Snakes can't boost when they're this short
+    pub boost_min_length: usize,
+    /// How many turns each player can have queued up at once
+    pub turn_queue_length: usize,
+    /// What happens to the snakes of players who are disconnected but may come back
+    pub detached_snakes: DetachedSnakes,
+    /// Chance (out of `u32::max_value()`) that each segment of a snake that hit a wall turns into food
+    pub wall_death_food_probability: u32,
+    /// Chance (out of `u32::max_value()`) that each segment of a snake that hit a snake turns into food
+    pub body_death_food_probability: u32,
+}
+
+const MAX_SPAWN_ATTEMPTS: usize = 100;
+
+pub mod map_format;
 pub mod serializable_chacha;
+pub mod replay;
+pub mod tron;
 use serializable_chacha::SerializableChaCha20;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -81,10 +320,49 @@ pub struct SnakeGameState {
     pub board: Board,
     pub player_segments: BTreeMap<PlayerId, VecDeque<Coord>>,
     pub num_foods: u64,
+    pub scores: BTreeMap<PlayerId, PlayerScore>,
+    pub num_powerups: u64,
+    /// The tick at which each player's power-up effects wear off
+    pub effects: BTreeMap<PlayerId, BTreeMap<PowerUpKind, u64>>,
+    /// The authoritative direction of each snake, since its head's tile may be shared with another snake
+    pub headings: BTreeMap<PlayerId, Direction>,
+    /// The tick until which each newly spawned snake can't collide with other snakes
+    pub spawn_protection: BTreeMap<PlayerId, u64>,
+    /// Segments each snake has yet to grow, or to lose if negative, one per step
+    pub pending_growth: BTreeMap<PlayerId, i64>,
+    /// The tick after which each food with a lifetime disappears
+    pub food_expiry: BTreeMap<Coord, u64>,
+    /// How many rings in from the edge of the board are walled off, counting walls the board started with
+    pub arena_inset: usize,
+    pub teams: BTreeMap<PlayerId, TeamId>,
+    /// Everyone who's connected, whether or not they have a snake
+    pub players: BTreeSet<PlayerId>,
+    pub phase: RoundPhase,
+    /// The scores at the end of the last round
+    pub results: BTreeMap<PlayerId, PlayerScore>,
+    /// The tick from which each dead player can respawn
+    pub respawn_at: BTreeMap<PlayerId, u64>,
+    /// The players holding boost, and how many ticks they've boosted since they last paid a segment for it
+    pub boosting: BTreeMap<PlayerId, u64>,
+    /// Each snake's turns that have yet to take effect, one per tick
+    pub turn_queues: BTreeMap<PlayerId, VecDeque<Direction>>,
+    /// Players whose connections dropped, who the server is waiting on to come back
+    pub detached: BTreeSet<PlayerId>,
+    pub config: SnakeGameConfig,
 }
 
 /* ===== Methods ===== */
 
+/// 64-bit FNV-1a, which is simple enough to be identical on every platform
+pub fn fnv1a64(data: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for b in data {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
 impl Direction {
     pub fn radians(self) -> f64 {
         use Direction::*;
@@ -95,8 +373,15 @@ impl Direction {
             Up => 3.0 * TAU / 4.0,
         }
     }
+    /// Integer-only, unlike `delta_vec2`, so that the simulation steps identically on every platform
     pub fn delta_coord(self) -> Coord {
-        self.delta_vec2().round()
+        use Direction::*;
+        match self {
+            Right => signed_coord(1, 0),
+            Down => signed_coord(0, 1),
+            Left => signed_coord(-1, 0),
+            Up => signed_coord(0, -1),
+        }
     }
     pub fn delta_vec2(self) -> Vec2 {
         Vec2::from_angle(self.radians())
@@ -124,6 +409,7 @@ fn test_delta_coord() {
     use Direction::*;
     for dir in &[Up, Down, Left, Right] {
         println!("{:?}", dir.delta_coord());
+        assert_eq!(dir.delta_coord(), dir.delta_vec2().round());
     }
 }
 
@@ -207,25 +493,114 @@ impl Sub<Coord> for Coord {
 impl Index<Coord> for Board {
     type Output = Tile;
     fn index(&self, c: Coord) -> &Tile {
-        let idx = self.idx_of_coord(c);
-        &self.tiles[idx]
+        let (width, height) = (self.width, self.height);
+        self.get(c).unwrap_or_else(|| panic!("{:?} is outside of the {}x{} board", c, width, height))
     }
 }
 impl IndexMut<Coord> for Board {
     fn index_mut(&mut self, c: Coord) -> &mut Tile {
-        let idx = self.idx_of_coord(c);
-        &mut self.tiles[idx]
+        let (width, height) = (self.width, self.height);
+        self.get_mut(c).unwrap_or_else(|| panic!("{:?} is outside of the {}x{} board", c, width, height))
+    }
+}
+
+impl fmt::Display for BoardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BoardError::SizeMismatch { width, height, tiles } => write!(f, "a {}x{} board can't have {} tiles", width, height, tiles),
+            BoardError::TooSmall { width, height } => write!(f, "a {}x{} board has no room for anything", width, height),
+            BoardError::OutOfBounds(c) => write!(f, "{:?} is outside of the board", c),
+            BoardError::MalformedSnake(pid) => write!(f, "the snake of {:?} is malformed", pid),
+        }
+    }
+}
+
+impl fmt::Debug for SessionToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SessionToken(..)")
     }
 }
 
 impl Board {
-    pub fn idx_of_coord(&self, c: Coord) -> usize {
-        c.y as usize * self.width + c.x as usize
+    pub fn idx_of_coord(&self, c: Coord) -> Option<usize> {
+        if self.contains(c) {
+            Some(c.y as usize * self.width + c.x as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn contains(&self, c: Coord) -> bool {
+        0 <= c.x && (c.x as usize) < self.width && 0 <= c.y && (c.y as usize) < self.height
+    }
+
+    pub fn get(&self, c: Coord) -> Option<&Tile> {
+        self.idx_of_coord(c).and_then(move |idx| self.tiles.get(idx))
+    }
+
+    pub fn get_mut(&mut self, c: Coord) -> Option<&mut Tile> {
+        match self.idx_of_coord(c) {
+            Some(idx) => self.tiles.get_mut(idx),
+            None => None,
+        }
+    }
+
+    /// The tile at `c`, treating everything off the board as wall
+    pub fn tile_or_wall(&self, c: Coord) -> Tile {
+        self.get(c).cloned().unwrap_or(Tile::Wall)
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item=&[Tile]> {
+        self.tiles.chunks(self.width.max(1)).take(self.height)
+    }
+
+    pub fn coords(&self) -> impl Iterator<Item=Coord> {
+        let width = self.width;
+        (0..self.height).flat_map(move |y| (0..width).map(move |x| coord(x, y)))
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item=(Coord, &Tile)> {
+        self.coords().zip(self.tiles.iter())
+    }
+
+    /// The coordinates `inset` tiles in from the nearest edge of the board
+    pub fn ring(&self, inset: usize) -> impl Iterator<Item=Coord> {
+        let (width, height) = (self.width, self.height);
+        self.coords().filter(move |c| {
+            let (x, y) = (c.x as usize, c.y as usize);
+            x.min(y).min(width - 1 - x).min(height - 1 - y) == inset
+        })
+    }
+
+    /// The coordinates of every tile matching `predicate`, e.g. `board.coords_where(|t| *t == Tile::Food(FoodKind::Normal))`
+    pub fn coords_where<'a, F: 'a+Fn(&Tile) -> bool>(&'a self, predicate: F) -> impl 'a+Iterator<Item=Coord> {
+        self.cells().filter(move |(_, tile)| predicate(tile)).map(|(c, _)| c)
+    }
+
+    /// Checks the invariants that a deserialized board might not uphold
+    pub fn validate(&self) -> Result<(), BoardError> {
+        if self.width == 0 || self.height == 0 {
+            return Err(BoardError::TooSmall { width: self.width, height: self.height });
+        }
+        if self.tiles.len() != self.width * self.height {
+            return Err(BoardError::SizeMismatch { width: self.width, height: self.height, tiles: self.tiles.len() });
+        }
+        if let Some(c) = self.spawn_points.iter().find(|c| !self.contains(**c)) {
+            return Err(BoardError::OutOfBounds(*c));
+        }
+        Ok(())
     }
 
     pub fn new(width: usize, height: usize) -> Board {
+        Board::with_topology(width, height, Topology::Walled)
+    }
+
+    pub fn with_topology(width: usize, height: usize, topology: Topology) -> Board {
         let tiles = vec![Tile::Empty; width * height];
-        let mut ret = Board { width, height, tiles };
+        let mut ret = Board { width, height, tiles, topology, spawn_points: vec![] };
+        if topology == Topology::Toroidal {
+            return ret;
+        }
         for i in 0..width {
             ret[coord(i, 0)] = Tile::Wall;
             ret[coord(i, height-1)] = Tile::Wall;
@@ -237,132 +612,1410 @@ impl Board {
         ret
     }
 
-    pub fn move_head(&mut self, c: Coord) -> (Vec<SnakeGameEvent>, Option<Coord>) {
-        use Tile::*;
-        let mut ret = (vec![], None);
-        if let WormSegment { pid, dir } = self[c] {
-            let c2 = c.offset(dir);
-            match self[c2] {
-                Empty => {
-                    self[c2] = WormSegment { pid, dir };
-                    ret.1 = Some(c2);
-                },
-                Wall => {
-                    ret.0.push(SnakeGameEvent::PlayerDied(pid, (0.1 * u32::max_value() as f64) as u32));
-                },
-                WormSegment { pid: _, dir: _ } => {
-                    ret.0.push(SnakeGameEvent::PlayerDied(pid, (0.9 * u32::max_value() as f64) as u32));
-                },
-                Food => {
-                    self[c2] = WormSegment { pid, dir };
-                    ret.0.push(SnakeGameEvent::PlayerAteFood(pid, c2));
-                    ret.1 = Some(c2);
-                },
-            }
+    /// Maps a coordinate that may have stepped off the board back onto it, for topologies where that's meaningful
+    pub fn normalize(&self, c: Coord) -> Coord {
+        match self.topology {
+            Topology::Walled => c,
+            Topology::Toroidal => signed_coord(c.x.rem_euclid(self.width as isize), c.y.rem_euclid(self.height as isize)),
+        }
+    }
+
+    pub fn step(&self, c: Coord, dir: Direction) -> Coord {
+        self.normalize(c.offset(dir))
+    }
+
+    /// Whether stepping from `from` to the adjacent tile `to` wraps around an edge of the board
+    pub fn crosses_edge(&self, from: Coord, to: Coord) -> bool {
+        let d = to - from;
+        d.x.abs() + d.y.abs() > 1
+    }
+
+    /// The direction that leads from `from` to the adjacent tile `to`, if there is one
+    pub fn direction_between(&self, from: Coord, to: Coord) -> Option<Direction> {
+        use Direction::*;
+        [Up, Down, Left, Right].iter().cloned().find(|dir| self.step(from, *dir) == to)
+    }
+
+    /// The number of steps between `a` and `b`, taking wrapping into account
+    pub fn distance(&self, a: Coord, b: Coord) -> usize {
+        let (mut dx, mut dy) = ((a.x - b.x).unsigned_abs(), (a.y - b.y).unsigned_abs());
+        if self.topology == Topology::Toroidal {
+            dx = dx.min(self.width - dx);
+            dy = dy.min(self.height - dy);
+        }
+        dx + dy
+    }
+}
+
+impl Default for SnakeGameConfig {
+    fn default() -> SnakeGameConfig {
+        SnakeGameConfig {
+            width: 40,
+            height: 30,
+            seed: 0xdeadbeefdeadbeef,
+            food_per_player: 1,
+            food_base: 2,
+            normal_food: FoodSpec { weight: 16, growth: 1, lifetime: None },
+            golden_food: FoodSpec { weight: 2, growth: 4, lifetime: Some(60) },
+            rotten_food: FoodSpec { weight: 3, growth: -2, lifetime: Some(60) },
+            starting_length: 1,
+            spawn_lookahead: 3,
+            spawn_protection_ticks: 0,
+            topology: Topology::Walled,
+            powerup_count: 1,
+            powerup_duration: 40,
+            shrink_others_amount: 3,
+            map: None,
+            arena_shrink: None,
+            team_count: 0,
+            friendly_fire: FriendlyFire::PassThrough,
+            rounds: None,
+            respawn_cooldown: Some(8),
+            boost_cost_interval: 2,
+            boost_min_length: 2,
+            turn_queue_length: 3,
+            detached_snakes: DetachedSnakes::KeepMoving,
+            wall_death_food_probability: u32::MAX / 10,
+            body_death_food_probability: u32::MAX / 10 * 9,
+        }
+    }
+}
+
+impl SnakeGameConfig {
+    /// Chance (out of `u32::max_value()`) that each segment of a snake that died of `cause` turns into food
+    pub fn food_drop_probability(&self, cause: DeathCause) -> u32 {
+        match cause {
+            DeathCause::Wall | DeathCause::ArenaClosed => self.wall_death_food_probability,
+            DeathCause::OwnBody | DeathCause::OtherBody | DeathCause::HeadOn => self.body_death_food_probability,
+            DeathCause::Disconnect => 0,
+        }
+    }
+
+    /// The board that every round starts on
+    pub fn initial_board(&self) -> Board {
+        self.map.clone().unwrap_or_else(|| Board::with_topology(self.width, self.height, self.topology))
+    }
+
+    pub fn food_spec(&self, kind: FoodKind) -> &FoodSpec {
+        match kind {
+            FoodKind::Normal => &self.normal_food,
+            FoodKind::Golden => &self.golden_food,
+            FoodKind::Rotten => &self.rotten_food,
+        }
+    }
+
+    /// How many segments moving onto `tile` adds to a snake
+    pub fn growth(&self, tile: Tile) -> i64 {
+        match tile {
+            Tile::Food(kind) => self.food_spec(kind).growth,
+            _ => 0,
         }
-        ret
     }
 }
 
 impl GameState for SnakeGameState {
     type PlayerInput = SnakePlayerInput;
     type GameEvent = SnakeGameEvent;
-    type S2CMsg = ServerToClient;
-    type C2SMsg = ClientToServer;
+    type Config = SnakeGameConfig;
 
-    fn new() -> SnakeGameState {
-        SnakeGameState {
-            rng: SeedableRng::seed_from_u64(0xdeadbeefdeadbeef),
+    fn new(config: SnakeGameConfig) -> SnakeGameState {
+        let mut state = SnakeGameState {
+            rng: SeedableRng::seed_from_u64(config.seed),
             tick: 0,
-            board: Board::new(40, 30),
+            board: config.initial_board(),
             player_segments: BTreeMap::new(),
             num_foods: 0,
-        }
+            scores: BTreeMap::new(),
+            num_powerups: 0,
+            effects: BTreeMap::new(),
+            headings: BTreeMap::new(),
+            spawn_protection: BTreeMap::new(),
+            pending_growth: BTreeMap::new(),
+            food_expiry: BTreeMap::new(),
+            arena_inset: 0,
+            teams: BTreeMap::new(),
+            players: BTreeSet::new(),
+            phase: if config.rounds.is_some() { RoundPhase::WaitingForPlayers } else { RoundPhase::Running(0) },
+            results: BTreeMap::new(),
+            respawn_at: BTreeMap::new(),
+            boosting: BTreeMap::new(),
+            turn_queues: BTreeMap::new(),
+            detached: BTreeSet::new(),
+            config,
+        };
+        state.reset_board();
+        state
     }
 
-    fn tick(&mut self, inputs: &BTreeMap<PlayerId, Self::PlayerInput>) -> Vec<Self::GameEvent> {
-        for (pid, input) in inputs.iter() {
+    fn tick(&mut self, inputs: &BTreeMap<PlayerId, Vec<Self::PlayerInput>>) -> Vec<Self::GameEvent> {
+        for (pid, input) in inputs.iter().flat_map(|(pid, inputs)| inputs.iter().map(move |input| (pid, input))) {
             match input {
-                SnakePlayerInput::ChangeDirection(dir) => self.change_direction(*pid, *dir),
+                SnakePlayerInput::ChangeDirection(dir) => self.queue_turn(*pid, *dir),
+                // handled below, once it's known whether the round is running
+                SnakePlayerInput::Respawn => {},
+                SnakePlayerInput::SetBoost(true) => { self.boosting.entry(*pid).or_insert(0); },
+                SnakePlayerInput::SetBoost(false) => { self.boosting.remove(pid); },
             }
         }
-        let mut events = vec![];
-        for (_, segments) in self.player_segments.iter_mut() {
-            if let Some(head) = segments.back() {
-                let (new_events, new_segment) = self.board.move_head(*head);
-                if let Some(s) = new_segment {
-                    segments.push_back(s);
-                }
-                if segments.len() > 1 && new_events.iter().all(|e| match e { SnakeGameEvent::PlayerAteFood(_, _) => false, _ => true }) {
-                    self.board[segments.pop_front().unwrap()] = Tile::Empty;
-                }
+        let mut events = self.update_phase();
+        let round_start = match self.phase {
+            RoundPhase::Running(start) => start,
+            _ => {
+                self.tick += 1;
+                return events;
+            },
+        };
+        let tick = self.tick;
+        self.spawn_protection.retain(|_, until| *until > tick);
+        for effects in self.effects.values_mut() {
+            effects.retain(|_, until| *until > tick);
+        }
+        self.effects.retain(|_, effects| !effects.is_empty());
+        let old_scores = self.scores.clone();
+        for (pid, _) in inputs.iter().filter(|(_, inputs)| inputs.contains(&SnakePlayerInput::Respawn)) {
+            if self.can_respawn(*pid) && self.spawn_player(*pid) {
+                events.push(SnakeGameEvent::PlayerRespawned(*pid));
+            }
+        }
+        let expired: Vec<Coord> = self.food_expiry.iter().filter(|(_, until)| **until < tick).map(|(c, _)| *c).collect();
+        for c in expired {
+            self.food_expiry.remove(&c);
+            if let Tile::Food(kind) = self.board[c] {
+                self.board[c] = Tile::Empty;
+                self.num_foods -= 1;
+                events.push(SnakeGameEvent::FoodExpired(kind, c));
+            }
+        }
+        if let Some(shrink) = self.config.arena_shrink.filter(|_| self.arena_can_shrink()) {
+            let is_shrink_tick = |t: u64| t >= shrink.start && (t - shrink.start).is_multiple_of(shrink.interval.max(1));
+            let round_tick = tick - round_start;
+            if is_shrink_tick(round_tick + shrink.warning) {
+                events.push(SnakeGameEvent::ArenaShrinking(tick + shrink.warning));
+            }
+            if is_shrink_tick(round_tick) {
+                let new_events = self.shrink_arena();
+                self.apply_events(&new_events);
                 events.extend(new_events);
             }
         }
-        for event in events.iter() {
-            match event {
-                SnakeGameEvent::PlayerDied(pid, food_probability) => self.remove_player(*pid, *food_probability),
-                SnakeGameEvent::PlayerAteFood(_, _) => {
-                    // TODO: score?
-                    self.num_foods -= 1;
-                },
+        self.take_queued_turns();
+        let boosting: Vec<PlayerId> = self.boosting.keys().cloned().filter(|pid| self.player_segments.get(pid).is_some_and(|segments| segments.len() > self.config.boost_min_length.max(1))).collect();
+        // fast snakes get an extra step before everyone moves
+        let fast: BTreeSet<PlayerId> = self.player_segments.keys().cloned().filter(|pid| !self.is_frozen(*pid) && (self.has_effect(*pid, PowerUpKind::SpeedBoost) || boosting.contains(pid))).collect();
+        for movers in &[fast, self.player_segments.keys().cloned().filter(|pid| !self.is_frozen(*pid)).collect()] {
+            let new_events = self.move_snakes(movers);
+            self.apply_events(&new_events);
+            events.extend(new_events);
+        }
+        // snakes that died this tick are no longer boosting
+        for pid in boosting {
+            if let Some(ticks) = self.boosting.get_mut(&pid) {
+                *ticks += 1;
+                if *ticks >= self.config.boost_cost_interval {
+                    *ticks = 0;
+                    self.shed_tail(pid);
+                }
             }
         }
-        let n = self.player_segments.len() as u64 + 2;
-        while self.num_foods < n {
-            self.spawn_food();
+        self.update_lengths();
+        for (pid, score) in self.scores.iter() {
+            if old_scores.get(pid) != Some(score) {
+                events.push(SnakeGameEvent::ScoreChanged(*pid, *score));
+            }
         }
+        while self.num_foods < self.food_target() && self.spawn_food() {}
+        while self.num_powerups < self.config.powerup_count && self.spawn_powerup() {}
         self.tick += 1;
         events
     }
+
+    fn player_joined(&mut self, pid: PlayerId) {
+        self.add_player(pid);
+    }
+
+    fn current_tick(&self) -> u64 {
+        self.tick
+    }
+
+    fn player_left(&mut self, pid: PlayerId) {
+        self.remove_player(pid, DeathCause::Disconnect);
+    }
+
+    fn player_detached(&mut self, pid: PlayerId) {
+        self.detached.insert(pid);
+        // nobody's holding the key down anymore
+        self.boosting.remove(&pid);
+    }
+
+    fn player_reattached(&mut self, pid: PlayerId) {
+        self.detached.remove(&pid);
+    }
 }
 
 impl SnakeGameState {
-    pub fn spawn_player(&mut self, pid: PlayerId) {
-        let dir = Direction::from_u32(self.rng.next_u32());
-        loop {
-            let c = self.random_coord();
-            // TODO: reroll location if the spawn would be in danger in 2-3 ticks
-            if let Tile::Empty = self.board[c] {
-                self.board[c] = Tile::WormSegment { pid, dir };
-                self.player_segments.entry(pid).or_insert_with(|| VecDeque::new()).push_back(c);
+    /// Registers a newly connected player, who gets a snake right away unless there's no round for them to join yet
+    pub fn add_player(&mut self, pid: PlayerId) {
+        self.players.insert(pid);
+        match self.phase {
+            RoundPhase::Running(_) if self.config.rounds.is_some() => {},
+            RoundPhase::Countdown(_) | RoundPhase::Running(_) => { self.spawn_player(pid); },
+            RoundPhase::WaitingForPlayers | RoundPhase::RoundOver { .. } => {},
+        }
+    }
+
+    /// Rounds are elimination matches, so nobody respawns during one, including players who joined partway through
+    pub fn can_respawn(&self, pid: PlayerId) -> bool {
+        self.config.rounds.is_none()
+            && self.config.respawn_cooldown.is_some()
+            && self.players.contains(&pid)
+            && !self.player_segments.contains_key(&pid)
+            && self.respawn_at.get(&pid).is_none_or(|at| self.tick >= *at)
+    }
+
+    /// Moves to the next round phase if it's time to, at the start of a tick
+    fn update_phase(&mut self) -> Vec<SnakeGameEvent> {
+        let rules = match self.config.rounds {
+            Some(rules) => rules,
+            None => return vec![],
+        };
+        let enough_players = self.players.len() >= rules.min_players;
+        let new_phase = match self.phase {
+            RoundPhase::WaitingForPlayers if enough_players => RoundPhase::Countdown(self.tick + rules.countdown_ticks),
+            RoundPhase::Countdown(_) if !enough_players => RoundPhase::WaitingForPlayers,
+            RoundPhase::Countdown(start) if self.tick >= start => RoundPhase::Running(self.tick),
+            RoundPhase::Running(start) => {
+                // snakes on the same team are on the same side
+                let sides: BTreeSet<(Option<TeamId>, Option<PlayerId>)> = self.player_segments.keys()
+                    .map(|pid| match self.teams.get(pid) { Some(team) => (Some(*team), None), None => (None, Some(*pid)) })
+                    .collect();
+                let last_standing = if rules.min_players > 1 { 1 } else { 0 };
+                if sides.len() <= last_standing || rules.time_limit.is_some_and(|limit| self.tick >= start + limit) {
+                    let longest = self.player_segments.values().map(|segments| segments.len()).max();
+                    let leaders: Vec<PlayerId> = self.player_segments.iter().filter(|(_, segments)| Some(segments.len()) == longest).map(|(pid, _)| *pid).collect();
+                    let leading_teams: BTreeSet<Option<TeamId>> = leaders.iter().map(|pid| self.teams.get(pid).cloned()).collect();
+                    let winner = match (&leaders[..], leading_teams.iter().next()) {
+                        (_, Some(Some(team))) if leading_teams.len() == 1 => Some(Winner::Team(*team)),
+                        ([pid], _) => Some(Winner::Player(*pid)),
+                        _ => None,
+                    };
+                    self.results = self.scores.clone();
+                    RoundPhase::RoundOver { winner, restart_at: self.tick + rules.round_over_ticks }
+                } else {
+                    self.phase
+                }
+            },
+            RoundPhase::RoundOver { restart_at, .. } if self.tick >= restart_at && enough_players => RoundPhase::Countdown(self.tick + rules.countdown_ticks),
+            RoundPhase::RoundOver { restart_at, .. } if self.tick >= restart_at => RoundPhase::WaitingForPlayers,
+            phase => phase,
+        };
+        if new_phase == self.phase {
+            return vec![];
+        }
+        if let RoundPhase::Countdown(_) = new_phase {
+            self.reset_board();
+            self.scores.clear();
+            for pid in self.players.clone() {
+                self.spawn_player(pid);
+            }
+        }
+        self.phase = new_phase;
+        vec![SnakeGameEvent::RoundPhaseChanged(new_phase)]
+    }
+
+    /// Puts a fresh board from the config in play, with no snakes on it
+    fn reset_board(&mut self) {
+        self.board = self.config.initial_board();
+        let board = &self.board;
+        self.num_foods = board.coords_where(|tile| matches!(tile, Tile::Food(_))).count() as u64;
+        self.food_expiry = board.cells()
+            .filter_map(|(c, tile)| match tile { Tile::Food(kind) => self.config.food_spec(*kind).lifetime.map(|lifetime| (c, self.tick + lifetime)), _ => None })
+            .collect();
+        self.num_powerups = 0;
+        self.arena_inset = (0..).find(|inset| !board.ring(*inset).all(|c| board[c] == Tile::Wall) || board.ring(*inset).next().is_none()).unwrap();
+        self.player_segments.clear();
+        self.headings.clear();
+        self.spawn_protection.clear();
+        self.effects.clear();
+        self.pending_growth.clear();
+        self.respawn_at.clear();
+        self.boosting.clear();
+        self.turn_queues.clear();
+    }
+
+    /// Gives `pid` a snake, unless there's nowhere it fits, in which case they're left to try respawning later
+    pub fn spawn_player(&mut self, pid: PlayerId) -> bool {
+        let length = self.config.starting_length.max(1);
+        let lookahead = self.config.spawn_lookahead;
+        let max_clearance = lookahead.max(self.board.width.max(self.board.height) - 1);
+        let mut best: Option<(usize, Direction, VecDeque<Coord>)> = None;
+        // keep the safest spawn found so far, so that a crowded board still gets a snake if there's room for one
+        for _ in 0..MAX_SPAWN_ATTEMPTS {
+            let head = self.random_spawn_coord();
+            let first_dir = self.rng.next_u32();
+            for i in 0..4 {
+                let dir = Direction::from_u32(first_dir.wrapping_add(i));
+                if let Some(segments) = self.snake_body(head, dir, length) {
+                    let clearance = self.clearance(pid, head, dir, max_clearance);
+                    if best.as_ref().is_none_or(|(best_clearance, _, _)| clearance > *best_clearance) {
+                        best = Some((clearance, dir, segments));
+                    }
+                }
+            }
+            if best.as_ref().is_some_and(|(clearance, _, _)| *clearance >= lookahead) {
+                break
+            }
+        }
+        let (dir, segments) = match best {
+            Some((_, dir, segments)) => (dir, segments),
+            None => return false,
+        };
+        for c in segments.iter() {
+            self.board[*c] = Tile::WormSegment { pid, dir };
+        }
+        if self.config.team_count > 0 && !self.teams.contains_key(&pid) {
+            // join the smallest team, breaking ties by the lowest `TeamId`
+            let team = (0..self.config.team_count).map(TeamId).min_by_key(|team| self.teams.values().filter(|team2| *team2 == team).count()).unwrap();
+            self.teams.insert(pid, team);
+        }
+        self.player_segments.insert(pid, segments);
+        self.headings.insert(pid, dir);
+        self.respawn_at.remove(&pid);
+        if self.config.spawn_protection_ticks > 0 {
+            self.spawn_protection.insert(pid, self.tick + self.config.spawn_protection_ticks);
+        }
+        true
+    }
+
+    /// The empty tiles that a snake of `length` with its head at `head` facing `dir` would occupy, from tail to head
+    fn snake_body(&self, head: Coord, dir: Direction, length: usize) -> Option<VecDeque<Coord>> {
+        let mut c = head;
+        let mut segments = VecDeque::new();
+        while let Some(Tile::Empty) = self.board.get(c) {
+            if segments.contains(&c) {
                 break
             }
+            segments.push_front(c);
+            if segments.len() == length {
+                return Some(segments);
+            }
+            c = self.board.step(c, -dir);
+        }
+        None
+    }
+
+    /// How many ticks a snake at `head` facing `dir` can go straight before it could run into a wall or a snake, up to `max`
+    fn clearance(&self, pid: PlayerId, head: Coord, dir: Direction, max: usize) -> usize {
+        let mut c = head;
+        for i in 0..max {
+            c = self.board.step(c, dir);
+            let threatened = self.player_segments.iter().any(|(pid2, segments)| *pid2 != pid && segments.back().is_some_and(|head2| self.board.distance(*head2, c) <= i + 1));
+            match self.board.tile_or_wall(c) {
+                Tile::Empty | Tile::Food(_) | Tile::PowerUp(_) if !threatened => {},
+                _ => return i,
+            }
+        }
+        max
+    }
+
+    pub fn is_spawn_protected(&self, pid: PlayerId) -> bool {
+        self.spawn_protection.get(&pid).is_some_and(|until| self.tick < *until)
+    }
+
+    /// Whether `pid`'s snake is waiting in place for them to reconnect
+    pub fn is_frozen(&self, pid: PlayerId) -> bool {
+        self.config.detached_snakes == DetachedSnakes::Freeze && self.detached.contains(&pid)
+    }
+
+    pub fn has_effect(&self, pid: PlayerId, kind: PowerUpKind) -> bool {
+        self.effects.get(&pid).and_then(|effects| effects.get(&kind)).is_some_and(|until| self.tick < *until)
+    }
+
+    pub fn are_teammates(&self, pid: PlayerId, other: PlayerId) -> bool {
+        self.teams.get(&pid).is_some_and(|team| self.teams.get(&other) == Some(team))
+    }
+
+    /// Whether `pid`'s head running into `other`'s snake is fatal; a snake can never pass through itself
+    pub fn collides(&self, pid: PlayerId, other: PlayerId) -> bool {
+        let intangible = |pid| self.is_spawn_protected(pid) || self.has_effect(pid, PowerUpKind::Ghost);
+        let friendly = self.config.friendly_fire == FriendlyFire::PassThrough && self.are_teammates(pid, other);
+        pid == other || !(intangible(pid) || intangible(other) || friendly || self.has_effect(pid, PowerUpKind::Shield))
+    }
+
+    /// Each team's members' scores added together, except `peak_length`, which is the longest any member has been
+    pub fn team_scores(&self) -> BTreeMap<TeamId, PlayerScore> {
+        let mut ret: BTreeMap<TeamId, PlayerScore> = BTreeMap::new();
+        for (pid, team) in self.teams.iter() {
+            let (total, score) = (ret.entry(*team).or_default(), self.scores.get(pid).cloned().unwrap_or_default());
+            total.food_eaten += score.food_eaten;
+            total.length += score.length;
+            total.peak_length = total.peak_length.max(score.peak_length);
+            total.kills += score.kills;
+        }
+        ret
+    }
+
+    fn apply_events(&mut self, events: &[SnakeGameEvent]) {
+        for event in events.iter() {
+            match event {
+                SnakeGameEvent::PlayerDied(pid, cause, killer) => {
+                    self.remove_player(*pid, *cause);
+                    if let Some(killer) = killer {
+                        self.scores.entry(*killer).or_default().kills += 1;
+                    }
+                },
+                SnakeGameEvent::PlayerAteFood(pid, _, _) => {
+                    self.scores.entry(*pid).or_default().food_eaten += 1;
+                },
+                SnakeGameEvent::PlayerGotPowerUp(pid, kind, _) => {
+                    if *kind == PowerUpKind::ShrinkOthers {
+                        let others: Vec<PlayerId> = self.player_segments.keys().cloned().filter(|pid2| pid2 != pid).collect();
+                        for pid2 in others {
+                            self.shrink(pid2, self.config.shrink_others_amount);
+                        }
+                    } else {
+                        self.effects.entry(*pid).or_default().insert(*kind, self.tick + self.config.powerup_duration);
+                    }
+                },
+                SnakeGameEvent::PlayerRespawned(_) | SnakeGameEvent::FoodExpired(_, _) | SnakeGameEvent::ArenaShrinking(_)
+                    | SnakeGameEvent::RoundPhaseChanged(_) | SnakeGameEvent::ScoreChanged(_, _) => {},
+            }
+        }
+    }
+
+    /// Takes up to `amount` segments off of `pid`'s tail, always leaving the head
+    fn shrink(&mut self, pid: PlayerId, amount: usize) {
+        for _ in 0..amount {
+            let tail = match self.player_segments.get_mut(&pid) {
+                Some(segments) if segments.len() > 1 => segments.pop_front().unwrap(),
+                _ => return,
+            };
+            if let Tile::WormSegment { pid: pid2, .. } = self.board[tail] {
+                if pid == pid2 {
+                    self.repaint(tail);
+                }
+            }
+        }
+    }
+
+    /// How much food each tick tops the board up to
+    pub fn food_target(&self) -> u64 {
+        self.config.food_per_player * self.player_segments.len() as u64 + self.config.food_base
+    }
+
+    /// Stops short of leaving less open area than the food target needs
+    pub fn arena_can_shrink(&self) -> bool {
+        let min_size = self.config.arena_shrink.map_or(0, |shrink| shrink.min_size.max(1));
+        let closed = 2 * (self.arena_inset + 1);
+        self.board.width >= closed + min_size && self.board.height >= closed + min_size
+            && ((self.board.width - closed) * (self.board.height - closed)) as u64 >= self.food_target()
+    }
+
+    /// Walls off the outermost open ring of the arena, killing every snake with a segment in it
+    fn shrink_arena(&mut self) -> Vec<SnakeGameEvent> {
+        let ring: BTreeSet<Coord> = self.board.ring(self.arena_inset).collect();
+        self.arena_inset += 1;
+        let events = self.player_segments.iter()
+            .filter(|(_, segments)| segments.iter().any(|c| ring.contains(c)))
+            .map(|(pid, _)| SnakeGameEvent::PlayerDied(*pid, DeathCause::ArenaClosed, None))
+            .collect();
+        // the caller removes the dead snakes before anything else can see them
+        for c in ring {
+            match self.board[c] {
+                Tile::Food(_) => self.num_foods -= 1,
+                Tile::PowerUp(_) => self.num_powerups -= 1,
+                _ => {},
+            }
+            self.food_expiry.remove(&c);
+            self.board[c] = Tile::Wall;
+        }
+        events
+    }
+
+    /// Takes one segment off of `pid`'s tail, leaving food in its place
+    fn shed_tail(&mut self, pid: PlayerId) {
+        if let Some(tail) = self.player_segments.get(&pid).and_then(|segments| segments.front().cloned()) {
+            self.shrink(pid, 1);
+            if self.board[tail] == Tile::Empty {
+                self.place_food(tail, FoodKind::Normal);
+            }
         }
     }
 
+    /// Moves each of `movers`' snakes one tile at once, so that the outcome doesn't depend on the order of the
+    /// `PlayerId`s:
+    /// - snakes whose heads land on the same tile, or pass through each other, all die
+    /// - moving onto a tail that's being vacated this tick is allowed
+    /// - collisions that `collides` rules out are ignored, so snakes can share tiles
+    fn move_snakes(&mut self, movers: &BTreeSet<PlayerId>) -> Vec<SnakeGameEvent> {
+        let mut targets = BTreeMap::new();
+        for (pid, segments) in self.player_segments.iter().filter(|(pid, _)| movers.contains(pid)) {
+            if let (Some(head), Some(dir)) = (segments.back(), self.headings.get(pid)) {
+                targets.insert(*pid, (*head, *dir, self.board.step(*head, *dir)));
+            }
+        }
+        // a snake's tail stays put while it has growth pending, including on the tick that it eats, whether or not
+        // it survives the tick
+        let growing: BTreeSet<PlayerId> = targets.iter()
+            .filter(|(pid, (_, _, target))| self.pending_growth.get(pid).cloned().unwrap_or(0) + self.config.growth(self.board.tile_or_wall(*target)) > 0)
+            .map(|(pid, _)| *pid)
+            .collect();
+        let mut occupants: BTreeMap<Coord, Vec<PlayerId>> = BTreeMap::new();
+        for (pid, segments) in self.player_segments.iter() {
+            let vacating = if targets.contains_key(pid) && !growing.contains(pid) { 1 } else { 0 };
+            for c in segments.iter().skip(vacating) {
+                occupants.entry(*c).or_default().push(*pid);
+            }
+        }
+
+        let mut deaths = BTreeMap::new();
+        for (pid, (head, _, target)) in targets.iter() {
+            let rivals: Vec<PlayerId> = targets.iter()
+                .filter(|(pid2, (head2, _, target2))| pid2 != &pid && (target2 == target || (target2 == head && head2 == target)))
+                .map(|(pid2, _)| *pid2)
+                .filter(|pid2| self.collides(*pid, *pid2))
+                .collect();
+            if !rivals.is_empty() {
+                let killer = if rivals.len() == 1 { Some(rivals[0]) } else { None };
+                deaths.insert(*pid, (DeathCause::HeadOn, killer));
+                continue;
+            }
+            if self.board.tile_or_wall(*target) == Tile::Wall {
+                deaths.insert(*pid, (DeathCause::Wall, None));
+                continue;
+            }
+            match occupants.get(target).and_then(|pids| pids.iter().find(|pid2| self.collides(*pid, **pid2))) {
+                Some(pid2) if pid2 == pid => { deaths.insert(*pid, (DeathCause::OwnBody, None)); },
+                Some(pid2) => { deaths.insert(*pid, (DeathCause::OtherBody, Some(*pid2))); },
+                None => {},
+            }
+        }
+
+        let mut events = vec![];
+        let mut vacated = vec![];
+        for pid in targets.keys().filter(|pid| !growing.contains(pid)) {
+            vacated.push((*pid, self.player_segments.get_mut(pid).unwrap().pop_front().unwrap()));
+        }
+        let contents: BTreeMap<PlayerId, Tile> = targets.iter().map(|(pid, (_, _, target))| (*pid, self.board.tile_or_wall(*target))).collect();
+        // snakes that pass through each other can share a food or power-up, but it only leaves the board once
+        let mut consumed = BTreeMap::new();
+        for (pid, (_, dir, target)) in targets.iter() {
+            if let Some((cause, killer)) = deaths.get(pid) {
+                events.push(SnakeGameEvent::PlayerDied(*pid, *cause, *killer));
+                continue;
+            }
+            match contents[pid] {
+                Tile::Food(kind) => events.push(SnakeGameEvent::PlayerAteFood(*pid, kind, *target)),
+                Tile::PowerUp(kind) => events.push(SnakeGameEvent::PlayerGotPowerUp(*pid, kind, *target)),
+                _ => {},
+            }
+            consumed.insert(*target, contents[pid]);
+            self.board[*target] = Tile::WormSegment { pid: *pid, dir: *dir };
+            self.player_segments.get_mut(pid).unwrap().push_back(*target);
+            let pending = self.pending_growth.get(pid).cloned().unwrap_or(0) + self.config.growth(contents[pid]) - growing.contains(pid) as i64;
+            self.set_pending_growth(*pid, pending);
+        }
+        for (pid, tail) in vacated {
+            if let Tile::WormSegment { pid: pid2, .. } = self.board[tail] {
+                if pid == pid2 {
+                    self.repaint(tail);
+                }
+            }
+        }
+        // a snake owed a loss gives up one extra tail segment per step, or as many as it can spare
+        let shrinking: Vec<(PlayerId, i64)> = self.pending_growth.iter().filter(|(pid, n)| **n < 0 && targets.contains_key(pid) && !deaths.contains_key(pid)).map(|(pid, n)| (*pid, *n)).collect();
+        for (pid, pending) in shrinking {
+            self.shrink(pid, 1);
+            self.set_pending_growth(pid, pending + 1);
+        }
+        for (c, tile) in consumed.iter() {
+            match tile {
+                Tile::Food(_) => {
+                    self.num_foods -= 1;
+                    self.food_expiry.remove(c);
+                },
+                Tile::PowerUp(_) => self.num_powerups -= 1,
+                _ => {},
+            }
+        }
+        events
+    }
+
+    fn set_pending_growth(&mut self, pid: PlayerId, pending: i64) {
+        if pending == 0 {
+            self.pending_growth.remove(&pid);
+        } else {
+            self.pending_growth.insert(pid, pending);
+        }
+    }
+
+    /// Redraws the tile at `c` after the snake drawn there left it, showing any other snake that's still on it
+    fn repaint(&mut self, c: Coord) {
+        self.board[c] = Tile::Empty;
+        for (pid, segments) in self.player_segments.iter() {
+            if let Some(i) = segments.iter().position(|c2| *c2 == c) {
+                let dir = segments.get(i+1).and_then(|next| self.board.direction_between(c, *next)).or_else(|| self.headings.get(pid).cloned());
+                if let Some(dir) = dir {
+                    self.board[c] = Tile::WormSegment { pid: *pid, dir };
+                }
+            }
+        }
+    }
+
+    /// Queues `dir` up behind `pid`'s other turns, unless it would be pointless because the snake will already be
+    /// heading that way or back the way it came by then, or the queue is full
+    pub fn queue_turn(&mut self, pid: PlayerId, dir: Direction) {
+        let heading = match self.headings.get(&pid) {
+            Some(heading) => *heading,
+            None => return,
+        };
+        let queue = self.turn_queues.entry(pid).or_default();
+        let last = queue.back().cloned().unwrap_or(heading);
+        if dir != last && dir != -last && queue.len() < self.config.turn_queue_length.max(1) {
+            queue.push_back(dir);
+        }
+    }
+
+    /// Turns every snake with a queued turn, at the start of a tick's movement
+    fn take_queued_turns(&mut self) {
+        let turns: Vec<(PlayerId, Direction)> = self.turn_queues.iter_mut().filter_map(|(pid, queue)| queue.pop_front().map(|dir| (*pid, dir))).collect();
+        self.turn_queues.retain(|_, queue| !queue.is_empty());
+        for (pid, dir) in turns {
+            self.change_direction(pid, dir);
+        }
+    }
+
+    /// Turns `pid`'s snake immediately, unless that would reverse it into itself
     pub fn change_direction(&mut self, pid: PlayerId, dir: Direction) {
-        if let Some(segments) = self.player_segments.get_mut(&pid) {
-            if let Some(head) = segments.back() {
-                if let Tile::WormSegment { pid: pid2, dir: dir2 } = &mut self.board[*head] {
-                    assert_eq!(pid, *pid2);
-                    if dir.delta_coord() + dir2.delta_coord() != coord(0, 0) {
-                        *dir2 = dir;
+        if let Some(heading) = self.headings.get_mut(&pid) {
+            if dir != -*heading {
+                *heading = dir;
+                if let Some(head) = self.player_segments.get(&pid).and_then(|segments| segments.back()) {
+                    if let Tile::WormSegment { pid: pid2, dir: dir2 } = &mut self.board[*head] {
+                        if pid == *pid2 {
+                            *dir2 = dir;
+                        }
                     }
                 }
             }
         }
     }
 
-    pub fn remove_player(&mut self, pid: PlayerId, food_probability: u32) {
+    pub fn remove_player(&mut self, pid: PlayerId, cause: DeathCause) {
+        let food_probability = self.config.food_drop_probability(cause);
+        self.headings.remove(&pid);
+        self.spawn_protection.remove(&pid);
+        self.effects.remove(&pid);
+        self.pending_growth.remove(&pid);
+        self.boosting.remove(&pid);
+        self.turn_queues.remove(&pid);
+        if cause == DeathCause::Disconnect {
+            self.teams.remove(&pid);
+            self.players.remove(&pid);
+            self.scores.remove(&pid);
+            self.respawn_at.remove(&pid);
+            self.detached.remove(&pid);
+        } else if let Some(cooldown) = self.config.respawn_cooldown {
+            self.respawn_at.insert(pid, self.tick + cooldown);
+        }
         if let Some(segments) = self.player_segments.remove(&pid) {
             for segment in segments {
-                self.board[segment] = if self.rng.next_u32() < food_probability { self.num_foods += 1; Tile::Food } else { Tile::Empty };
+                match self.board[segment] {
+                    Tile::WormSegment { pid: pid2, .. } if pid == pid2 => self.repaint(segment),
+                    _ => continue,
+                }
+                if self.board[segment] == Tile::Empty && self.rng.next_u32() < food_probability {
+                    self.place_food(segment, FoodKind::Normal);
+                }
             }
         }
     }
+
+    /// Checks that a deserialized snapshot is consistent enough to simulate without panicking
+    pub fn validate(&self) -> Result<(), BoardError> {
+        self.board.validate()?;
+        // the board is rebuilt from the config at the start of every round
+        match &self.config.map {
+            Some(map) => map.validate()?,
+            None if self.config.width == 0 || self.config.height == 0 => return Err(BoardError::TooSmall { width: self.config.width, height: self.config.height }),
+            None => {},
+        }
+        for (pid, segments) in self.player_segments.iter() {
+            if let Some(c) = segments.iter().find(|c| !self.board.contains(**c)) {
+                return Err(BoardError::OutOfBounds(*c));
+            }
+            if segments.is_empty() || !self.headings.contains_key(pid) || segments.iter().any(|c| !matches!(self.board[*c], Tile::WormSegment { .. })) {
+                return Err(BoardError::MalformedSnake(*pid));
+            }
+        }
+        if let Some(c) = self.food_expiry.keys().find(|c| !self.board.contains(**c)) {
+            return Err(BoardError::OutOfBounds(*c));
+        }
+        Ok(())
+    }
+
+    /// Only called from `tick`, so that every client's scores stay in lockstep
+    fn update_lengths(&mut self) {
+        for pid in self.player_segments.keys() {
+            self.scores.entry(*pid).or_default();
+        }
+        for (pid, score) in self.scores.iter_mut() {
+            score.length = self.player_segments.get(pid).map_or(0, |segments| segments.len() as u64);
+            score.peak_length = score.peak_length.max(score.length);
+        }
+    }
+
     pub fn random_coord(&mut self) -> Coord {
         coord(self.rng.next_u32() as usize % self.board.width, self.rng.next_u32() as usize % self.board.height)
     }
 
-    pub fn spawn_food(&mut self) {
-        loop {
-            let c = self.random_coord();
-            if let Tile::Empty = self.board[c] {
-                self.board[c] = Tile::Food;
-                self.num_foods += 1;
+    /// Falls back to anywhere on the board once every spawn point has been walled off or has a snake on it
+    pub fn random_spawn_coord(&mut self) -> Coord {
+        let open: Vec<Coord> = self.board.spawn_points.iter().cloned().filter(|c| !matches!(self.board.tile_or_wall(*c), Tile::Wall | Tile::WormSegment { .. })).collect();
+        match open.len() {
+            0 => self.random_coord(),
+            n => open[self.rng.next_u32() as usize % n],
+        }
+    }
+
+    /// `None` once there are no empty tiles left on the board
+    pub fn random_empty_coord(&mut self) -> Option<Coord> {
+        let empty: Vec<Coord> = self.board.coords_where(|tile| *tile == Tile::Empty).collect();
+        match empty.len() {
+            0 => None,
+            n => Some(empty[self.rng.next_u32() as usize % n]),
+        }
+    }
+
+    /// Returns false, placing nothing, when the board has no room left
+    pub fn spawn_powerup(&mut self) -> bool {
+        use PowerUpKind::*;
+        let kind = [SpeedBoost, Ghost, ShrinkOthers, Shield][self.rng.next_u32() as usize % 4];
+        match self.random_empty_coord() {
+            Some(c) => {
+                self.board[c] = Tile::PowerUp(kind);
+                self.num_powerups += 1;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Returns false, placing nothing, when the board has no room left
+    pub fn spawn_food(&mut self) -> bool {
+        use FoodKind::*;
+        let kinds = [Normal, Golden, Rotten];
+        let total: u64 = kinds.iter().map(|kind| self.config.food_spec(*kind).weight as u64).sum();
+        let mut roll = self.rng.next_u32() as u64 % total.max(1);
+        let mut kind = Normal;
+        for k in kinds.iter() {
+            let weight = self.config.food_spec(*k).weight as u64;
+            if roll < weight {
+                kind = *k;
                 break
             }
+            roll -= weight;
+        }
+        match self.random_empty_coord() {
+            Some(c) => {
+                self.place_food(c, kind);
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn place_food(&mut self, c: Coord, kind: FoodKind) {
+        self.board[c] = Tile::Food(kind);
+        self.num_foods += 1;
+        match self.config.food_spec(kind).lifetime {
+            Some(lifetime) => { self.food_expiry.insert(c, self.tick + lifetime); },
+            None => { self.food_expiry.remove(&c); },
+        }
+    }
+}
+
+#[test]
+fn test_toroidal_wraparound() {
+    let mut state = SnakeGameState::new(SnakeGameConfig { width: 5, height: 5, topology: Topology::Toroidal, food_per_player: 0, food_base: 0, powerup_count: 0, ..SnakeGameConfig::default() });
+    let pid = PlayerId(0);
+    place_snake(&mut state, pid, Direction::Right, &[coord(4, 2)]);
+    state.tick(&BTreeMap::new());
+    assert_eq!(state.player_segments[&pid], vec![coord(0, 2)]);
+    state.change_direction(pid, Direction::Up);
+    state.tick(&BTreeMap::new());
+    state.tick(&BTreeMap::new());
+    state.tick(&BTreeMap::new());
+    assert_eq!(state.player_segments[&pid], vec![coord(0, 4)]);
+    assert!(state.board.crosses_edge(coord(0, 0), coord(0, 4)));
+}
+
+#[cfg(test)]
+fn test_config() -> SnakeGameConfig {
+    SnakeGameConfig { width: 10, height: 10, food_per_player: 0, food_base: 0, powerup_count: 0, ..SnakeGameConfig::default() }
+}
+
+#[cfg(test)]
+fn test_state() -> SnakeGameState {
+    SnakeGameState::new(test_config())
+}
+
+#[cfg(test)]
+fn place_snake(state: &mut SnakeGameState, pid: PlayerId, dir: Direction, segments: &[Coord]) {
+    for c in segments.iter() {
+        state.board[*c] = Tile::WormSegment { pid, dir };
+    }
+    state.player_segments.insert(pid, segments.iter().cloned().collect());
+    state.headings.insert(pid, dir);
+}
+
+#[cfg(test)]
+fn died(events: &[SnakeGameEvent], pid: PlayerId) -> bool {
+    events.iter().any(|e| match e { SnakeGameEvent::PlayerDied(pid2, _, _) => *pid2 == pid, _ => false })
+}
+
+#[test]
+fn test_scores() {
+    let mut state = test_state();
+    let (a, b) = (PlayerId(0), PlayerId(1));
+    place_snake(&mut state, a, Direction::Right, &[coord(2, 5), coord(3, 5)]);
+    place_snake(&mut state, b, Direction::Up, &[coord(4, 8), coord(4, 7)]);
+    state.place_food(coord(4, 5), FoodKind::Normal);
+    let events = state.tick(&BTreeMap::new());
+    assert!(events.contains(&SnakeGameEvent::ScoreChanged(a, PlayerScore { food_eaten: 1, length: 3, peak_length: 3, kills: 0 })), "{:?}", events);
+    assert!(events.contains(&SnakeGameEvent::ScoreChanged(b, PlayerScore { food_eaten: 0, length: 2, peak_length: 2, kills: 0 })), "{:?}", events);
+    // b runs into a's body
+    let events = state.tick(&BTreeMap::new());
+    assert!(events.contains(&SnakeGameEvent::PlayerDied(b, DeathCause::OtherBody, Some(a))), "{:?}", events);
+    assert!(events.contains(&SnakeGameEvent::ScoreChanged(a, PlayerScore { food_eaten: 1, length: 3, peak_length: 3, kills: 1 })), "{:?}", events);
+    assert!(events.contains(&SnakeGameEvent::ScoreChanged(b, PlayerScore { food_eaten: 0, length: 0, peak_length: 2, kills: 0 })), "{:?}", events);
+    assert!(state.tick(&BTreeMap::new()).is_empty());
+    // disconnected players' scores don't pile up forever
+    state.player_left(b);
+    assert_eq!(state.scores.keys().cloned().collect::<Vec<_>>(), vec![a]);
+}
+
+#[test]
+fn test_race_for_tile_kills_both() {
+    for &(a, b) in &[(PlayerId(0), PlayerId(1)), (PlayerId(1), PlayerId(0))] {
+        for &food in &[false, true] {
+            let mut state = test_state();
+            place_snake(&mut state, a, Direction::Right, &[coord(2, 5), coord(3, 5)]);
+            place_snake(&mut state, b, Direction::Up, &[coord(4, 7), coord(4, 6)]);
+            if food {
+                state.board[coord(4, 5)] = Tile::Food(FoodKind::Normal);
+                state.num_foods = 1;
+            }
+            let events = state.tick(&BTreeMap::new());
+            assert!(died(&events, a) && died(&events, b), "{:?}", events);
+            assert!(state.player_segments.is_empty());
+        }
+    }
+}
+
+#[test]
+fn test_head_on_swap_kills_both() {
+    for &(a, b) in &[(PlayerId(0), PlayerId(1)), (PlayerId(1), PlayerId(0))] {
+        let mut state = test_state();
+        place_snake(&mut state, a, Direction::Right, &[coord(2, 5), coord(3, 5)]);
+        place_snake(&mut state, b, Direction::Left, &[coord(5, 5), coord(4, 5)]);
+        let events = state.tick(&BTreeMap::new());
+        assert!(events.contains(&SnakeGameEvent::PlayerDied(a, DeathCause::HeadOn, Some(b))), "{:?}", events);
+        assert!(events.contains(&SnakeGameEvent::PlayerDied(b, DeathCause::HeadOn, Some(a))), "{:?}", events);
+    }
+}
+
+#[test]
+fn test_follow_vacating_tail() {
+    for &(a, b) in &[(PlayerId(0), PlayerId(1)), (PlayerId(1), PlayerId(0))] {
+        let mut state = test_state();
+        place_snake(&mut state, a, Direction::Right, &[coord(3, 5), coord(4, 5)]);
+        place_snake(&mut state, b, Direction::Right, &[coord(1, 5), coord(2, 5)]);
+        let events = state.tick(&BTreeMap::new());
+        assert!(!died(&events, a) && !died(&events, b), "{:?}", events);
+        assert_eq!(state.player_segments[&a], vec![coord(4, 5), coord(5, 5)]);
+        assert_eq!(state.player_segments[&b], vec![coord(2, 5), coord(3, 5)]);
+
+        // a tail doesn't move on the tick that its snake eats
+        state.board[coord(6, 5)] = Tile::Food(FoodKind::Normal);
+        state.num_foods = 1;
+        let events = state.tick(&BTreeMap::new());
+        assert!(!died(&events, a), "{:?}", events);
+        assert!(events.contains(&SnakeGameEvent::PlayerDied(b, DeathCause::OtherBody, Some(a))), "{:?}", events);
+        assert_eq!(state.player_segments[&a], vec![coord(4, 5), coord(5, 5), coord(6, 5)]);
+    }
+}
+
+#[test]
+fn test_chase_own_tail() {
+    let mut state = test_state();
+    let pid = PlayerId(0);
+    place_snake(&mut state, pid, Direction::Up, &[coord(3, 3), coord(4, 3), coord(4, 4), coord(3, 4)]);
+    let events = state.tick(&BTreeMap::new());
+    assert!(!died(&events, pid), "{:?}", events);
+    assert_eq!(state.player_segments[&pid], vec![coord(4, 3), coord(4, 4), coord(3, 4), coord(3, 3)]);
+}
+
+#[test]
+fn test_death_causes() {
+    let (a, b) = (PlayerId(0), PlayerId(1));
+    for &(wall, body) in &[(u32::MAX, 0), (0, u32::MAX)] {
+        let fresh_state = || {
+            let mut state = test_state();
+            state.config.wall_death_food_probability = wall;
+            state.config.body_death_food_probability = body;
+            state
+        };
+        // how much food a snake leaves behind, given how many segments it has after its tail moves on
+        let drops = |probability: u32, length: u64| if probability == 0 { 0 } else { length };
+
+        let mut state = fresh_state();
+        place_snake(&mut state, a, Direction::Left, &[coord(2, 5), coord(1, 5)]);
+        let events = state.tick(&BTreeMap::new());
+        assert!(events.contains(&SnakeGameEvent::PlayerDied(a, DeathCause::Wall, None)), "{:?}", events);
+        assert_eq!(state.num_foods, drops(wall, 1));
+
+        let mut state = fresh_state();
+        place_snake(&mut state, a, Direction::Up, &[coord(2, 3), coord(3, 3), coord(4, 3), coord(4, 4), coord(3, 4)]);
+        let events = state.tick(&BTreeMap::new());
+        assert!(events.contains(&SnakeGameEvent::PlayerDied(a, DeathCause::OwnBody, None)), "{:?}", events);
+        assert_eq!(state.num_foods, drops(body, 4));
+
+        let mut state = fresh_state();
+        place_snake(&mut state, a, Direction::Right, &[coord(2, 5), coord(3, 5), coord(4, 5)]);
+        place_snake(&mut state, b, Direction::Up, &[coord(3, 7), coord(3, 6)]);
+        let events = state.tick(&BTreeMap::new());
+        assert!(events.contains(&SnakeGameEvent::PlayerDied(b, DeathCause::OtherBody, Some(a))), "{:?}", events);
+        assert!(!died(&events, a), "{:?}", events);
+        assert_eq!(state.num_foods, drops(body, 1));
+
+        let mut state = fresh_state();
+        place_snake(&mut state, a, Direction::Right, &[coord(2, 5), coord(3, 5)]);
+        place_snake(&mut state, b, Direction::Left, &[coord(5, 5), coord(4, 5)]);
+        let events = state.tick(&BTreeMap::new());
+        assert!(events.contains(&SnakeGameEvent::PlayerDied(a, DeathCause::HeadOn, Some(b))), "{:?}", events);
+        assert!(events.contains(&SnakeGameEvent::PlayerDied(b, DeathCause::HeadOn, Some(a))), "{:?}", events);
+        assert_eq!(state.num_foods, drops(body, 2));
+
+        // leaving never drops food, whatever the other probabilities are
+        let mut state = fresh_state();
+        place_snake(&mut state, a, Direction::Right, &[coord(2, 5), coord(3, 5)]);
+        state.player_left(a);
+        assert!(state.player_segments.is_empty());
+        assert_eq!(state.num_foods, 0);
+        assert!(state.board.tiles.iter().all(|tile| *tile == Tile::Empty || *tile == Tile::Wall));
+    }
+}
+
+#[test]
+fn test_spawn_faces_open_space() {
+    let mut state = SnakeGameState::new(SnakeGameConfig { width: 6, height: 30, starting_length: 2, spawn_lookahead: 5, food_per_player: 0, food_base: 0, powerup_count: 0, ..SnakeGameConfig::default() });
+    for i in 0..3 {
+        let pid = PlayerId(i);
+        state.spawn_player(pid);
+        let dir = state.headings[&pid];
+        assert!(dir == Direction::Up || dir == Direction::Down, "{:?}", dir);
+        assert_eq!(state.player_segments[&pid].len(), 2);
+        for _ in 0..5 {
+            assert!(!died(&state.tick(&BTreeMap::new()), pid));
+        }
+        state.remove_player(pid, DeathCause::Disconnect);
+    }
+}
+
+#[test]
+fn test_spawn_without_room() {
+    // longer than any line on the board
+    let mut state = SnakeGameState::new(SnakeGameConfig { starting_length: 11, ..test_config() });
+    state.add_player(PlayerId(0));
+    assert!(state.player_segments.is_empty());
+    let respawn = vec![(PlayerId(0), vec![SnakePlayerInput::Respawn])].into_iter().collect();
+    assert!(!state.tick(&respawn).contains(&SnakeGameEvent::PlayerRespawned(PlayerId(0))));
+    assert!(state.can_respawn(PlayerId(0)));
+}
+
+#[test]
+fn test_spawn_points() {
+    let mut state = test_state();
+    state.board.spawn_points = vec![coord(2, 2), coord(7, 7)];
+    place_snake(&mut state, PlayerId(0), Direction::Up, &[coord(2, 2)]);
+    for _ in 0..10 {
+        assert_eq!(state.random_spawn_coord(), coord(7, 7));
+    }
+    // with every spawn point taken, anywhere will do
+    place_snake(&mut state, PlayerId(1), Direction::Up, &[coord(7, 7)]);
+    assert!(state.spawn_player(PlayerId(2)));
+    assert!(!state.board.spawn_points.contains(state.player_segments[&PlayerId(2)].back().unwrap()));
+}
+
+#[test]
+fn test_spawn_protection() {
+    let mut state = SnakeGameState::new(SnakeGameConfig { spawn_protection_ticks: 2, ..test_config() });
+    let (a, b) = (PlayerId(0), PlayerId(1));
+    place_snake(&mut state, a, Direction::Right, &[coord(2, 5), coord(3, 5)]);
+    place_snake(&mut state, b, Direction::Up, &[coord(4, 6)]);
+    state.spawn_protection.insert(b, 2);
+    // b's head moves onto a's head, then a moves on through b
+    let events = state.tick(&BTreeMap::new());
+    assert!(!died(&events, a) && !died(&events, b), "{:?}", events);
+    let events = state.tick(&BTreeMap::new());
+    assert!(!died(&events, a) && !died(&events, b), "{:?}", events);
+    assert_eq!(state.player_segments[&a], vec![coord(4, 5), coord(5, 5)]);
+    assert_eq!(state.player_segments[&b], vec![coord(4, 4)]);
+    assert_eq!(state.board[coord(4, 5)], Tile::WormSegment { pid: a, dir: Direction::Right });
+    // protection has worn off
+    state.change_direction(a, Direction::Up);
+    state.change_direction(b, Direction::Right);
+    let events = state.tick(&BTreeMap::new());
+    assert!(events.contains(&SnakeGameEvent::PlayerDied(a, DeathCause::HeadOn, Some(b))), "{:?}", events);
+}
+
+#[test]
+fn test_golden_run() {
+    let mut state = SnakeGameState::new(SnakeGameConfig { starting_length: 3, spawn_protection_ticks: 4, ..SnakeGameConfig::default() });
+    let mut input_rng = SerializableChaCha20::seed_from_u64(1);
+    for _ in 0..2000 {
+        let mut inputs = BTreeMap::new();
+        for pid in (0..4).map(PlayerId) {
+            if !state.player_segments.contains_key(&pid) {
+                state.spawn_player(pid);
+            }
+            if input_rng.next_u32() % 4 == 0 {
+                inputs.insert(pid, vec![SnakePlayerInput::ChangeDirection(Direction::from_u32(input_rng.next_u32()))]);
+            }
         }
+        state.tick(&inputs);
+    }
+    // only what the simulation produces, so that new fields and config options don't move it
+    let projection = (state.tick, &state.board.tiles, &state.player_segments, state.rng.word_pos());
+    assert_eq!(fnv1a64(&bincode::serialize(&projection).unwrap()), 0x408ea6141e2b6adc);
+}
+
+#[test]
+fn test_board_bounds() {
+    let mut board = Board::new(4, 3);
+    assert!(board.contains(coord(3, 2)));
+    for c in &[coord(4, 0), coord(0, 3), signed_coord(-1, 1), signed_coord(1, -1)] {
+        assert!(!board.contains(*c));
+        assert_eq!(board.get(*c), None);
+        assert!(board.get_mut(*c).is_none());
+        assert_eq!(board.tile_or_wall(*c), Tile::Wall);
+    }
+    board[coord(1, 1)] = Tile::Food(FoodKind::Normal);
+    assert_eq!(board.coords_where(|t| *t == Tile::Food(FoodKind::Normal)).collect::<Vec<_>>(), vec![coord(1, 1)]);
+    assert_eq!(board.coords_where(|t| *t == Tile::Empty).count(), 1);
+    assert_eq!(board.rows().nth(1), Some(&[Tile::Wall, Tile::Food(FoodKind::Normal), Tile::Empty, Tile::Wall][..]));
+    assert!(board.validate().is_ok());
+    board.tiles.pop();
+    assert_eq!(board.validate(), Err(BoardError::SizeMismatch { width: 4, height: 3, tiles: 11 }));
+}
+
+#[test]
+fn test_validate_snapshot() {
+    let state = test_state();
+    assert!(state.validate().is_ok());
+    let mut bad = state.clone();
+    bad.food_expiry.insert(coord(10, 3), 5);
+    assert_eq!(bad.validate(), Err(BoardError::OutOfBounds(coord(10, 3))));
+    let mut bad = state.clone();
+    bad.config.height = 0;
+    assert_eq!(bad.validate(), Err(BoardError::TooSmall { width: 10, height: 0 }));
+    let mut bad = state.clone();
+    bad.config.map = Some(Board { tiles: vec![], ..Board::new(3, 3) });
+    assert_eq!(bad.validate(), Err(BoardError::SizeMismatch { width: 3, height: 3, tiles: 0 }));
+    // a snake has to be drawn on the board wherever it is
+    let mut bad = state.clone();
+    place_snake(&mut bad, PlayerId(0), Direction::Up, &[coord(2, 2)]);
+    bad.player_segments.get_mut(&PlayerId(0)).unwrap().push_front(coord(2, 3));
+    assert_eq!(bad.validate(), Err(BoardError::MalformedSnake(PlayerId(0))));
+}
+
+#[test]
+fn test_powerups() {
+    let mut state = SnakeGameState::new(SnakeGameConfig { width: 20, height: 20, ..test_config() });
+    let (a, b) = (PlayerId(0), PlayerId(1));
+    place_snake(&mut state, a, Direction::Right, &[coord(1, 5), coord(2, 5)]);
+    place_snake(&mut state, b, Direction::Down, &[coord(5, 1), coord(5, 2), coord(5, 3), coord(5, 4), coord(5, 5)]);
+    state.board[coord(3, 5)] = Tile::PowerUp(PowerUpKind::Ghost);
+    state.num_powerups = 1;
+    let events = state.tick(&BTreeMap::new());
+    assert!(events.contains(&SnakeGameEvent::PlayerGotPowerUp(a, PowerUpKind::Ghost, coord(3, 5))), "{:?}", events);
+    assert_eq!(state.num_powerups, 0);
+    // a passes through b's body
+    state.tick(&BTreeMap::new());
+    let events = state.tick(&BTreeMap::new());
+    assert!(!died(&events, a) && !died(&events, b), "{:?}", events);
+    assert_eq!(state.player_segments[&a].back(), Some(&coord(5, 5)));
+    let events = state.tick(&BTreeMap::new());
+    assert!(!died(&events, a) && !died(&events, b), "{:?}", events);
+    assert_eq!(state.board[coord(5, 5)], Tile::WormSegment { pid: a, dir: Direction::Right });
+
+    state.board[coord(7, 5)] = Tile::PowerUp(PowerUpKind::ShrinkOthers);
+    state.num_powerups = 1;
+    state.tick(&BTreeMap::new());
+    assert_eq!(state.player_segments[&b].len(), 2);
+    assert_eq!(state.board.coords_where(|t| match t { Tile::WormSegment { pid, .. } => *pid == b, _ => false }).count(), 2);
+
+    state.effects.entry(a).or_default().insert(PowerUpKind::SpeedBoost, state.tick + 1);
+    state.change_direction(a, Direction::Down);
+    state.tick(&BTreeMap::new());
+    assert_eq!(state.player_segments[&a], vec![coord(7, 6), coord(7, 7)]);
+    state.tick(&BTreeMap::new());
+    assert_eq!(state.player_segments[&a], vec![coord(7, 7), coord(7, 8)]);
+
+    // a board with less room than the power-up target fills up instead of hanging
+    let mut state = SnakeGameState::new(SnakeGameConfig { width: 4, height: 4, powerup_count: 10, ..test_config() });
+    state.tick(&BTreeMap::new());
+    assert_eq!(state.num_powerups, 4);
+}
+
+#[test]
+fn test_food_kinds() {
+    let config = test_config();
+    let mut state = SnakeGameState::new(SnakeGameConfig { width: 20, height: 20, golden_food: FoodSpec { lifetime: Some(2), ..config.golden_food }, ..config });
+    let (a, b) = (PlayerId(0), PlayerId(1));
+    place_snake(&mut state, a, Direction::Right, &[coord(1, 5), coord(2, 5)]);
+    place_snake(&mut state, b, Direction::Left, &[coord(18, 2), coord(17, 2), coord(16, 2), coord(15, 2)]);
+    state.place_food(coord(3, 5), FoodKind::Golden);
+    state.place_food(coord(14, 2), FoodKind::Rotten);
+    state.place_food(coord(7, 8), FoodKind::Golden);
+    let mut lengths = vec![];
+    for i in 0..5 {
+        let events = state.tick(&BTreeMap::new());
+        assert_eq!(events.contains(&SnakeGameEvent::FoodExpired(FoodKind::Golden, coord(7, 8))), i == 3, "{:?}", events);
+        lengths.push((state.player_segments[&a].len(), state.player_segments[&b].len()));
+    }
+    // golden food grows a snake by 4 over 4 ticks, and rotten food shrinks it by 2 over 2 ticks
+    assert_eq!(lengths, vec![(3, 3), (4, 2), (5, 2), (6, 2), (6, 2)]);
+    assert_eq!(state.board.coords_where(|t| match t { Tile::WormSegment { pid, .. } => *pid == b, _ => false }).count(), 2);
+    assert_eq!(state.num_foods, 0);
+    assert!(state.pending_growth.is_empty() && state.food_expiry.is_empty());
+}
+
+#[test]
+fn test_arena_shrink() {
+    let mut state = SnakeGameState::new(SnakeGameConfig { arena_shrink: Some(ArenaShrink { start: 2, interval: 3, warning: 1, min_size: 4 }), ..test_config() });
+    assert_eq!(state.arena_inset, 1);
+    let (a, b) = (PlayerId(0), PlayerId(1));
+    place_snake(&mut state, a, Direction::Up, &[coord(5, 7)]);
+    place_snake(&mut state, b, Direction::Down, &[coord(1, 1), coord(1, 2)]);
+    let mut all_events = vec![];
+    for _ in 0..12 {
+        all_events.push(state.tick(&BTreeMap::new()));
+    }
+    let warnings: Vec<u64> = all_events.iter().flatten().filter_map(|e| match e { SnakeGameEvent::ArenaShrinking(t) => Some(*t), _ => None }).collect();
+    assert_eq!(warnings, vec![2, 5]);
+    assert!(all_events[2].contains(&SnakeGameEvent::PlayerDied(b, DeathCause::ArenaClosed, None)), "{:?}", all_events[2]);
+    assert!(all_events[5].contains(&SnakeGameEvent::PlayerDied(a, DeathCause::ArenaClosed, None)), "{:?}", all_events[5]);
+    assert_eq!(state.arena_inset, 3);
+    assert!(state.board.ring(2).all(|c| state.board[c] == Tile::Wall));
+    assert_eq!(state.board.coords_where(|t| *t != Tile::Wall).count(), 16);
+}
+
+#[test]
+fn test_arena_shrink_to_minimum() {
+    let arena_shrink = Some(ArenaShrink { start: 1, interval: 1, warning: 0, min_size: 1 });
+    let mut state = SnakeGameState::new(SnakeGameConfig { width: 7, height: 7, arena_shrink, ..test_config() });
+    for _ in 0..10 {
+        state.tick(&BTreeMap::new());
+    }
+    assert_eq!(state.arena_inset, 3);
+    assert_eq!(state.board.coords_where(|t| *t != Tile::Wall).count(), 1);
+    // the arena stops closing while it still has room for the food, and the board keeps ticking
+    let mut state = SnakeGameState::new(SnakeGameConfig { width: 7, height: 7, arena_shrink, food_base: 2, powerup_count: 2, ..test_config() });
+    for _ in 0..10 {
+        state.tick(&BTreeMap::new());
+    }
+    assert_eq!(state.arena_inset, 2);
+    assert_eq!((state.num_foods, state.num_powerups), (2, 2));
+}
+
+#[test]
+fn test_teams() {
+    let mut state = SnakeGameState::new(SnakeGameConfig { width: 20, height: 20, team_count: 2, ..test_config() });
+    for pid in (0..3).map(PlayerId) {
+        state.spawn_player(pid);
+    }
+    assert_eq!(state.teams.values().cloned().collect::<Vec<_>>(), vec![TeamId(0), TeamId(1), TeamId(0)]);
+    state.remove_player(PlayerId(1), DeathCause::Disconnect);
+    state.spawn_player(PlayerId(3));
+    assert_eq!(state.teams[&PlayerId(3)], TeamId(1));
+
+    for &friendly_fire in &[FriendlyFire::PassThrough, FriendlyFire::Collide] {
+        let mut state = SnakeGameState::new(SnakeGameConfig { friendly_fire, ..test_config() });
+        let (a, b) = (PlayerId(0), PlayerId(1));
+        place_snake(&mut state, a, Direction::Right, &[coord(2, 5), coord(3, 5)]);
+        place_snake(&mut state, b, Direction::Left, &[coord(5, 5), coord(4, 5)]);
+        state.teams.insert(a, TeamId(0));
+        state.teams.insert(b, TeamId(0));
+        let events = state.tick(&BTreeMap::new());
+        assert_eq!(died(&events, a), friendly_fire == FriendlyFire::Collide, "{:?}", events);
+        assert_eq!(died(&events, b), friendly_fire == FriendlyFire::Collide, "{:?}", events);
+        if friendly_fire == FriendlyFire::PassThrough {
+            assert_eq!(state.team_scores()[&TeamId(0)], PlayerScore { food_eaten: 0, length: 4, peak_length: 2, kills: 0 });
+        }
+    }
+}
+
+#[test]
+fn test_round_lifecycle() {
+    let rules = RoundRules { min_players: 2, countdown_ticks: 2, time_limit: None, round_over_ticks: 3 };
+    let mut state = SnakeGameState::new(SnakeGameConfig { rounds: Some(rules), ..test_config() });
+    let (a, b) = (PlayerId(0), PlayerId(1));
+    state.add_player(a);
+    assert!(state.tick(&BTreeMap::new()).is_empty());
+    assert!(state.player_segments.is_empty());
+    state.add_player(b);
+    assert_eq!(state.tick(&BTreeMap::new()), vec![SnakeGameEvent::RoundPhaseChanged(RoundPhase::Countdown(3))]);
+    assert_eq!(state.player_segments.len(), 2);
+    let frozen = state.player_segments.clone();
+    state.tick(&BTreeMap::new());
+    assert_eq!(state.player_segments, frozen);
+    assert!(state.tick(&BTreeMap::new()).contains(&SnakeGameEvent::RoundPhaseChanged(RoundPhase::Running(3))));
+    assert_ne!(state.player_segments, frozen);
+
+    state.remove_player(b, DeathCause::Wall);
+    let winner = RoundPhase::RoundOver { winner: Some(Winner::Player(a)), restart_at: 7 };
+    assert_eq!(state.tick(&BTreeMap::new()), vec![SnakeGameEvent::RoundPhaseChanged(winner)]);
+    assert_eq!(state.results.keys().cloned().collect::<Vec<_>>(), vec![a, b]);
+    for _ in 0..2 {
+        assert!(state.tick(&BTreeMap::new()).is_empty());
+    }
+    assert_eq!(state.tick(&BTreeMap::new()), vec![SnakeGameEvent::RoundPhaseChanged(RoundPhase::Countdown(9))]);
+    assert_eq!(state.player_segments.len(), 2);
+    assert!(state.scores.is_empty());
+
+    // a round can't start without enough players
+    state.remove_player(b, DeathCause::Disconnect);
+    assert_eq!(state.tick(&BTreeMap::new()), vec![SnakeGameEvent::RoundPhaseChanged(RoundPhase::WaitingForPlayers)]);
+}
+
+#[test]
+fn test_team_round() {
+    let rules = RoundRules { min_players: 2, countdown_ticks: 2, time_limit: None, round_over_ticks: 3 };
+    let mut state = SnakeGameState::new(SnakeGameConfig { width: 20, height: 20, team_count: 2, rounds: Some(rules), ..test_config() });
+    for pid in (0..3).map(PlayerId) {
+        state.add_player(pid);
+    }
+    for _ in 0..3 {
+        state.tick(&BTreeMap::new());
+    }
+    assert_eq!(state.phase, RoundPhase::Running(2));
+    // the last team standing wins even though its two snakes are the same length
+    assert_eq!(state.teams[&PlayerId(1)], TeamId(1));
+    state.remove_player(PlayerId(1), DeathCause::Wall);
+    let winner = RoundPhase::RoundOver { winner: Some(Winner::Team(TeamId(0))), restart_at: 6 };
+    assert_eq!(state.tick(&BTreeMap::new()), vec![SnakeGameEvent::RoundPhaseChanged(winner)]);
+}
+
+#[test]
+fn test_respawn() {
+    let mut state = SnakeGameState::new(SnakeGameConfig { respawn_cooldown: Some(2), ..test_config() });
+    let (a, b) = (PlayerId(0), PlayerId(1));
+    state.add_player(a);
+    state.add_player(b);
+    state.remove_player(a, DeathCause::Wall);
+    state.remove_player(b, DeathCause::Disconnect);
+    let respawn: BTreeMap<PlayerId, Vec<SnakePlayerInput>> = vec![(a, vec![SnakePlayerInput::Respawn]), (b, vec![SnakePlayerInput::Respawn])].into_iter().collect();
+    for _ in 0..2 {
+        assert!(!state.tick(&respawn).contains(&SnakeGameEvent::PlayerRespawned(a)));
+    }
+    assert_eq!(state.tick(&respawn), vec![SnakeGameEvent::PlayerRespawned(a), SnakeGameEvent::ScoreChanged(a, PlayerScore { food_eaten: 0, length: 1, peak_length: 1, kills: 0 })]);
+    assert_eq!(state.player_segments.keys().cloned().collect::<Vec<_>>(), vec![a]);
+    assert!(!state.can_respawn(a) && !state.can_respawn(b));
+}
+
+#[test]
+fn test_no_respawn_in_rounds() {
+    let rules = RoundRules { min_players: 2, countdown_ticks: 1, time_limit: None, round_over_ticks: 3 };
+    let mut state = SnakeGameState::new(SnakeGameConfig { width: 20, height: 20, rounds: Some(rules), respawn_cooldown: Some(0), ..test_config() });
+    let (a, late) = (PlayerId(0), PlayerId(3));
+    for pid in (0..3).map(PlayerId) {
+        state.add_player(pid);
+    }
+    state.tick(&BTreeMap::new());
+    state.tick(&BTreeMap::new());
+    assert_eq!(state.phase, RoundPhase::Running(1));
+    state.add_player(late);
+    state.remove_player(a, DeathCause::Wall);
+    let respawn: BTreeMap<PlayerId, Vec<SnakePlayerInput>> = vec![(a, vec![SnakePlayerInput::Respawn]), (late, vec![SnakePlayerInput::Respawn])].into_iter().collect();
+    let events = state.tick(&respawn);
+    assert!(!events.iter().any(|e| matches!(e, SnakeGameEvent::PlayerRespawned(_))), "{:?}", events);
+    assert_eq!(state.phase, RoundPhase::Running(1));
+    assert!(!state.can_respawn(a) && !state.can_respawn(late));
+}
+
+#[test]
+fn test_boost() {
+    let mut state = SnakeGameState::new(SnakeGameConfig { width: 20, height: 20, boost_cost_interval: 2, boost_min_length: 2, ..test_config() });
+    let a = PlayerId(0);
+    place_snake(&mut state, a, Direction::Right, &[coord(1, 5), coord(2, 5), coord(3, 5), coord(4, 5)]);
+    let mut inputs: BTreeMap<PlayerId, Vec<SnakePlayerInput>> = vec![(a, vec![SnakePlayerInput::SetBoost(true)])].into_iter().collect();
+    let mut steps = vec![];
+    for i in 0..7 {
+        if i == 2 {
+            // food on the tile a boosting snake passes over is still eaten
+            state.place_food(coord(9, 5), FoodKind::Normal);
+        }
+        let events = state.tick(&inputs);
+        assert_eq!(i == 2, events.contains(&SnakeGameEvent::PlayerAteFood(a, FoodKind::Normal, coord(9, 5))), "{:?}", events);
+        inputs.clear();
+        steps.push((state.player_segments[&a].back().unwrap().x, state.player_segments[&a].len()));
+    }
+    // every other tick of boosting costs a segment, until the snake is too short to boost
+    assert_eq!(steps, vec![(6, 4), (8, 3), (10, 4), (12, 3), (14, 3), (16, 2), (17, 2)]);
+    assert_eq!(state.num_foods, 3);
+    assert_eq!(state.board[coord(5, 5)], Tile::Food(FoodKind::Normal));
+}
+
+#[test]
+fn test_turn_queue() {
+    use Direction::*;
+    let mut state = test_state();
+    let a = PlayerId(0);
+    place_snake(&mut state, a, Right, &[coord(4, 5)]);
+    // a quick up-then-left takes two ticks instead of the left being rejected as a reversal
+    let turns = |dirs: &[Direction]| vec![(a, dirs.iter().map(|dir| SnakePlayerInput::ChangeDirection(*dir)).collect())].into_iter().collect();
+    state.tick(&turns(&[Up, Left]));
+    assert_eq!(state.player_segments[&a], vec![coord(4, 4)]);
+    state.tick(&BTreeMap::new());
+    assert_eq!(state.player_segments[&a], vec![coord(3, 4)]);
+    // reversals, repeats and turns past the end of the queue are dropped
+    state.tick(&turns(&[Right, Left, Down, Right, Up, Left]));
+    assert_eq!(state.turn_queues[&a], vec![Right, Up]);
+    assert_eq!(state.headings[&a], Down);
+}
+
+#[test]
+fn test_checksum() {
+    let mut a = SnakeGameState::new(SnakeGameConfig::default());
+    a.spawn_player(PlayerId(0));
+    let mut b: SnakeGameState = bincode::deserialize(&bincode::serialize(&a).unwrap()).unwrap();
+    assert_eq!(a.checksum(), b.checksum());
+    a.tick(&BTreeMap::new());
+    assert_ne!(a.checksum(), b.checksum());
+    b.tick(&BTreeMap::new());
+    assert_eq!(a.checksum(), b.checksum());
+    b.rng.next_u32();
+    assert_ne!(a.checksum(), b.checksum());
+}
+
+#[test]
+fn test_detached_snakes() {
+    for &(detached_snakes, moves) in &[(DetachedSnakes::KeepMoving, true), (DetachedSnakes::Freeze, false)] {
+        let mut state = SnakeGameState::new(SnakeGameConfig { detached_snakes, ..test_config() });
+        let pid = PlayerId(0);
+        state.player_joined(pid);
+        place_snake(&mut state, pid, Direction::Right, &[coord(2, 5), coord(3, 5)]);
+        state.boosting.insert(pid, 0);
+        state.player_detached(pid);
+        assert!(state.boosting.is_empty());
+        state.tick(&BTreeMap::new());
+        assert_eq!(state.player_segments[&pid].back() == Some(&coord(4, 5)), moves);
+        state.player_reattached(pid);
+        state.tick(&BTreeMap::new());
+        assert_eq!(state.player_segments[&pid].back(), Some(&if moves { coord(5, 5) } else { coord(4, 5) }));
+        state.player_detached(pid);
+        state.player_left(pid);
+        assert!(state.detached.is_empty() && !state.player_segments.contains_key(&pid));
     }
 }
@@ -0,0 +1,144 @@
+//! A human-editable text format for arenas: a header of `key: value` lines, a `---` separator, then one line of
+//! tiles per row of the board:
+//!
+//! ```text
+//! topology: walled
+//! ---
+//! ##########
+//! #S......*#
+//! #..####..#
+//! #*......S#
+//! ##########
+//! ```
+//!
+//! `#` is a wall, `.` is empty, `*` is normal food, and `S` is an empty tile that snakes may spawn on. If a map has any
+//! `S` tiles, snakes only spawn on those.
+
+use super::{coord, Board, FoodKind, Tile, Topology};
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MapParseError {
+    /// 1-based, like a text editor's
+    pub line: usize,
+    /// 1-based, like a text editor's
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for MapParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+fn error<T>(line: usize, column: usize, message: String) -> Result<T, MapParseError> {
+    Err(MapParseError { line: line + 1, column: column + 1, message })
+}
+
+impl Board {
+    pub fn parse_map(text: &str) -> Result<Board, MapParseError> {
+        let mut topology = Topology::Walled;
+        let mut lines = text.lines().enumerate();
+        loop {
+            let (i, line) = match lines.next() {
+                Some(x) => x,
+                None => return error(0, 0, "missing the \"---\" line between the header and the tiles".into()),
+            };
+            if line.trim() == "---" {
+                break
+            }
+            if line.trim().is_empty() {
+                continue
+            }
+            let colon = match line.find(':') {
+                Some(colon) => colon,
+                None => return error(i, 0, format!("expected \"key: value\", found {:?}", line)),
+            };
+            let value = line[colon+1..].trim();
+            match line[..colon].trim() {
+                "topology" => topology = match value {
+                    "walled" => Topology::Walled,
+                    "toroidal" => Topology::Toroidal,
+                    _ => return error(i, colon + 1, format!("unknown topology {:?}", value)),
+                },
+                key => return error(i, 0, format!("unknown header key {:?}", key)),
+            }
+        }
+
+        let mut width = None;
+        let mut tiles = vec![];
+        let mut spawn_points = vec![];
+        let mut height = 0;
+        for (i, line) in lines {
+            let line = line.trim_end();
+            if line.is_empty() {
+                continue
+            }
+            let mut row_width = 0;
+            for (j, c) in line.chars().enumerate() {
+                tiles.push(match c {
+                    '#' => Tile::Wall,
+                    '.' => Tile::Empty,
+                    '*' => Tile::Food(FoodKind::Normal),
+                    'S' => { spawn_points.push(coord(j, height)); Tile::Empty },
+                    _ => return error(i, j, format!("unknown tile {:?}", c)),
+                });
+                row_width += 1;
+            }
+            match width {
+                None => width = Some(row_width),
+                Some(width) if width != row_width => return error(i, row_width.min(width), format!("row is {} tiles wide, but the first row is {}", row_width, width)),
+                Some(_) => {},
+            }
+            height += 1;
+        }
+        let width = match width {
+            Some(width) => width,
+            None => return error(text.lines().count(), 0, "the map has no tiles".into()),
+        };
+        Ok(Board { width, height, tiles, topology, spawn_points })
+    }
+
+    /// The inverse of `parse_map`; snakes aren't part of a map, so they're written as empty tiles
+    pub fn to_map_string(&self) -> String {
+        let mut ret = String::new();
+        ret.push_str(match self.topology {
+            Topology::Walled => "topology: walled\n",
+            Topology::Toroidal => "topology: toroidal\n",
+        });
+        ret.push_str("---\n");
+        for (c, tile) in self.cells() {
+            ret.push(match tile {
+                Tile::Wall => '#',
+                Tile::Food(_) => '*',
+                _ if self.spawn_points.contains(&c) => 'S',
+                _ => '.',
+            });
+            if c.x as usize == self.width - 1 {
+                ret.push('\n');
+            }
+        }
+        ret
+    }
+}
+
+#[test]
+fn test_map_roundtrip() {
+    let text = "topology: toroidal\n---\n#S..\n.*.#\n";
+    let board = Board::parse_map(text).unwrap();
+    assert_eq!((board.width, board.height, board.topology), (4, 2, Topology::Toroidal));
+    assert_eq!(board[coord(1, 1)], Tile::Food(FoodKind::Normal));
+    assert_eq!(board.spawn_points, vec![coord(1, 0)]);
+    assert_eq!(board.to_map_string(), text);
+}
+
+#[test]
+fn test_map_errors() {
+    let err = |text| Board::parse_map(text).unwrap_err();
+    assert_eq!((err("---\n###\n#x#\n").line, err("---\n###\n#x#\n").column), (3, 2));
+    assert_eq!((err("---\n###\n##\n").line, err("---\n###\n##\n").column), (3, 3));
+    assert_eq!(err("\ntopology: flat\n---\n#\n").line, 2);
+    assert_eq!(err("#\n").line, 1);
+    assert_eq!(err("---\n").message, "the map has no tiles");
+}
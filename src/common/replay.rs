@@ -0,0 +1,209 @@
+//! Recordings of whole matches. The simulation is lockstep, so a match is just the world it started from plus
+//! everything the server fed it afterwards: joins, leaves, dropped connections and each tick's inputs. A replay file is a `ReplayHeader`,
+//! the starting world, then bincode'd `ReplayRecord`s until the end of the file. Every tick carries the server's
+//! checksum from just before it, so playback can point at the first tick that came out differently.
+
+use super::{GameState, PlayerId};
+use std::collections::BTreeMap;
+use std::{fmt, io};
+
+pub const REPLAY_MAGIC: [u8; 8] = *b"SNKREPLY";
+/// Bumped whenever the layout of the file, or of any game's state or inputs, changes
+pub const REPLAY_VERSION: u32 = 3;
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ReplayHeader {
+    pub magic: [u8; 8],
+    pub version: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(bound = "")]
+pub enum ReplayRecord<G: GameState> {
+    PlayerJoined(PlayerId),
+    PlayerLeft(PlayerId),
+    PlayerDetached(PlayerId),
+    PlayerReattached(PlayerId),
+    /// `checksum` is of the world as of `tick`, before `inputs`
+    Tick { tick: u64, inputs: BTreeMap<PlayerId, Vec<G::PlayerInput>>, checksum: u64 },
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    Decode(bincode::Error),
+    NotAReplay,
+    UnsupportedVersion(u32),
+    /// Playback got to `tick` with a different world than the one that was recorded
+    Desync { tick: u64, expected: u64, actual: u64 },
+    /// The records skip or repeat ticks
+    OutOfOrder { expected: u64, found: u64 },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ReplayError::*;
+        match self {
+            Io(e) => write!(f, "{}", e),
+            Decode(e) => write!(f, "malformed replay: {}", e),
+            NotAReplay => write!(f, "not a replay file"),
+            UnsupportedVersion(v) => write!(f, "replay version {} isn't supported (this build reads version {})", v, REPLAY_VERSION),
+            Desync { tick, expected, actual } => write!(f, "desync at tick {}: recorded checksum {:016x}, replayed {:016x}", tick, expected, actual),
+            OutOfOrder { expected, found } => write!(f, "expected a record for tick {}, found tick {}", expected, found),
+        }
+    }
+}
+
+impl From<bincode::Error> for ReplayError {
+    fn from(e: bincode::Error) -> ReplayError {
+        match *e {
+            bincode::ErrorKind::Io(e) => ReplayError::Io(e),
+            _ => ReplayError::Decode(e),
+        }
+    }
+}
+
+/// Appends a match to `W` as it happens; the caller decides when to flush
+#[derive(Debug)]
+pub struct ReplayWriter<W: io::Write> {
+    out: W,
+}
+
+impl<W: io::Write> ReplayWriter<W> {
+    pub fn new<G: GameState>(mut out: W, initial: &G) -> Result<ReplayWriter<W>, ReplayError> {
+        bincode::serialize_into(&mut out, &ReplayHeader { magic: REPLAY_MAGIC, version: REPLAY_VERSION })?;
+        bincode::serialize_into(&mut out, initial)?;
+        Ok(ReplayWriter { out })
+    }
+
+    pub fn record<G: GameState>(&mut self, record: &ReplayRecord<G>) -> Result<(), ReplayError> {
+        Ok(bincode::serialize_into(&mut self.out, record)?)
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Replay<G: GameState> {
+    pub initial: G,
+    pub records: Vec<ReplayRecord<G>>,
+}
+
+impl<G: GameState> Replay<G> {
+    /// A file that was cut off partway through a record, say because the server died, reads up to the last whole one
+    pub fn read_from<R: io::Read>(mut input: R) -> Result<Replay<G>, ReplayError> {
+        let header: ReplayHeader = bincode::deserialize_from(&mut input)?;
+        if header.magic != REPLAY_MAGIC {
+            return Err(ReplayError::NotAReplay);
+        }
+        if header.version != REPLAY_VERSION {
+            return Err(ReplayError::UnsupportedVersion(header.version));
+        }
+        let initial = bincode::deserialize_from(&mut input)?;
+        let mut records = vec![];
+        loop {
+            match bincode::deserialize_from(&mut input) {
+                Ok(record) => records.push(record),
+                Err(e) => match *e {
+                    bincode::ErrorKind::Io(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                    _ => return Err(e.into()),
+                },
+            }
+        }
+        Ok(Replay { initial, records })
+    }
+
+    pub fn play(&self) -> Replayer<'_, G> {
+        Replayer { state: self.initial.clone(), records: self.records.iter() }
+    }
+
+    /// Plays the whole match, checking every tick, and returns the final world
+    pub fn verify(&self) -> Result<G, ReplayError> {
+        let mut replayer = self.play();
+        for result in &mut replayer {
+            result?;
+        }
+        Ok(replayer.state)
+    }
+}
+
+/// Steps through a `Replay` one tick at a time, yielding each tick's number and events
+pub struct Replayer<'a, G: GameState> {
+    pub state: G,
+    records: std::slice::Iter<'a, ReplayRecord<G>>,
+}
+
+impl<'a, G: GameState> Iterator for Replayer<'a, G> {
+    type Item = Result<(u64, Vec<G::GameEvent>), ReplayError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.records.next()? {
+                ReplayRecord::PlayerJoined(pid) => self.state.player_joined(*pid),
+                ReplayRecord::PlayerLeft(pid) => self.state.player_left(*pid),
+                ReplayRecord::PlayerDetached(pid) => self.state.player_detached(*pid),
+                ReplayRecord::PlayerReattached(pid) => self.state.player_reattached(*pid),
+                ReplayRecord::Tick { tick, inputs, checksum } => {
+                    if *tick != self.state.current_tick() {
+                        return Some(Err(ReplayError::OutOfOrder { expected: self.state.current_tick(), found: *tick }));
+                    }
+                    let actual = self.state.checksum();
+                    if actual != *checksum {
+                        return Some(Err(ReplayError::Desync { tick: *tick, expected: *checksum, actual }));
+                    }
+                    return Some(Ok((*tick, self.state.tick(inputs))));
+                },
+            }
+        }
+    }
+}
+
+#[test]
+fn test_replay_roundtrip() {
+    use super::{Direction, SnakeGameConfig, SnakeGameState, SnakePlayerInput};
+    let mut state = SnakeGameState::new(SnakeGameConfig::default());
+    let mut writer = ReplayWriter::new(vec![], &state).unwrap();
+    let mut record = |state: &mut SnakeGameState, record: ReplayRecord<SnakeGameState>| {
+        writer.record(&record).unwrap();
+        match record {
+            ReplayRecord::PlayerJoined(pid) => state.player_joined(pid),
+            ReplayRecord::PlayerLeft(pid) => state.player_left(pid),
+            ReplayRecord::PlayerDetached(pid) => state.player_detached(pid),
+            ReplayRecord::PlayerReattached(pid) => state.player_reattached(pid),
+            ReplayRecord::Tick { inputs, .. } => { state.tick(&inputs); },
+        }
+    };
+    record(&mut state, ReplayRecord::PlayerJoined(PlayerId(0)));
+    record(&mut state, ReplayRecord::PlayerJoined(PlayerId(1)));
+    for i in 0..40u32 {
+        let inputs = vec![(PlayerId(i as usize % 2), vec![SnakePlayerInput::ChangeDirection(Direction::from_u32(i))])].into_iter().collect();
+        let tick = ReplayRecord::Tick { tick: state.tick, inputs, checksum: state.checksum() };
+        record(&mut state, tick);
+    }
+    record(&mut state, ReplayRecord::PlayerLeft(PlayerId(0)));
+    let tick = ReplayRecord::Tick { tick: state.tick, inputs: BTreeMap::new(), checksum: state.checksum() };
+    record(&mut state, tick);
+    let bytes = writer.get_mut().clone();
+
+    let replay = Replay::<SnakeGameState>::read_from(&bytes[..]).unwrap();
+    assert_eq!(replay.records.len(), 44);
+    assert_eq!(replay.play().count(), 41);
+    assert_eq!(replay.verify().unwrap().checksum(), state.checksum());
+    // a truncated file plays up to where it was cut off
+    assert_eq!(Replay::<SnakeGameState>::read_from(&bytes[..bytes.len() - 1]).unwrap().records.len(), 43);
+
+    let mut tampered = replay.clone();
+    if let ReplayRecord::Tick { inputs, .. } = &mut tampered.records[12] {
+        inputs.insert(PlayerId(1), vec![SnakePlayerInput::SetBoost(true)]);
+    }
+    match tampered.verify() {
+        Err(ReplayError::Desync { tick, .. }) => assert_eq!(tick, 11),
+        x => panic!("expected a desync, got {:?}", x.map(|_| ())),
+    }
+
+    let mut old = bytes.clone();
+    old[8] = 0;
+    assert!(matches!(Replay::<SnakeGameState>::read_from(&old[..]), Err(ReplayError::UnsupportedVersion(0))));
+    assert!(matches!(Replay::<SnakeGameState>::read_from(&b"not a replay, honest"[..]), Err(ReplayError::NotAReplay)));
+}
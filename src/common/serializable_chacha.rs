@@ -52,7 +52,20 @@ impl RngCore for SerializableChaCha20 {
 
 impl SeedableRng for SerializableChaCha20 {
     type Seed = <rand_chacha::ChaCha20Rng as SeedableRng>::Seed;
-    fn from_seed(seed: Self::Seed) -> Self { SerializableChaCha20 { seed: seed.clone(), rng: rand_chacha::ChaCha20Rng::from_seed(seed) } }
+    fn from_seed(seed: Self::Seed) -> Self {
+        let mut rng = rand_chacha::ChaCha20Rng::from_seed(seed);
+        // a generator that hasn't filled its first block underflows in `get_word_pos`; seeking to the start fills it
+        // without changing the stream
+        rng.set_word_pos(0);
+        SerializableChaCha20 { seed, rng }
+    }
+}
+
+impl SerializableChaCha20 {
+    /// How many 32-bit words of the stream have been used
+    pub fn word_pos(&self) -> u128 {
+        self.rng.get_word_pos()
+    }
 }
 
 #[test]
@@ -61,3 +74,13 @@ fn test_chacha_size() {
     let tmp = T::seed_from_u64(0xdeadbeefdeadbeef);
     println!("{:?} {:?}", mem::size_of_val(&tmp), mem::size_of::<T>());
 }
+
+#[test]
+fn test_chacha_roundtrip() {
+    let mut rng = SerializableChaCha20::seed_from_u64(0xdeadbeefdeadbeef);
+    let fresh: SerializableChaCha20 = bincode::deserialize(&bincode::serialize(&rng).unwrap()).unwrap();
+    assert_eq!(fresh.clone().next_u64(), rng.clone().next_u64());
+    assert_eq!(rng.next_u64(), rand_chacha::ChaCha20Rng::seed_from_u64(0xdeadbeefdeadbeef).next_u64());
+    let mut copy: SerializableChaCha20 = bincode::deserialize(&bincode::serialize(&rng).unwrap()).unwrap();
+    assert_eq!(copy.next_u64(), rng.next_u64());
+}
@@ -0,0 +1,159 @@
+//! Light cycles: every cycle leaves a solid trail, and a cycle that runs into anything is derezzed, taking its trail
+//! with it, and comes back somewhere else on the next tick. Small on purpose; it mostly keeps the server honest about
+//! only going through `GameState`. There's no browser client for it, so it's played by bots over the websocket.
+
+use super::{coord, Board, Coord, Direction, GameState, PlayerId, Tile, MAX_SPAWN_ATTEMPTS};
+use super::serializable_chacha::SerializableChaCha20;
+use rand::{RngCore, SeedableRng};
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TronInput {
+    Turn(Direction),
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TronEvent {
+    Spawned(PlayerId),
+    Crashed(PlayerId),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TronConfig {
+    pub width: usize,
+    pub height: usize,
+    pub seed: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TronGameState {
+    pub rng: SerializableChaCha20,
+    pub tick: u64,
+    /// Trails are drawn as `Tile::WormSegment`s
+    pub board: Board,
+    /// Where each cycle is and which way it's going; players who just crashed don't have one
+    pub cycles: BTreeMap<PlayerId, (Coord, Direction)>,
+    pub players: BTreeSet<PlayerId>,
+    pub config: TronConfig,
+}
+
+impl Default for TronConfig {
+    fn default() -> TronConfig {
+        TronConfig { width: 40, height: 30, seed: 0xdeadbeefdeadbeef }
+    }
+}
+
+impl GameState for TronGameState {
+    type PlayerInput = TronInput;
+    type GameEvent = TronEvent;
+    type Config = TronConfig;
+
+    fn new(config: TronConfig) -> TronGameState {
+        TronGameState {
+            rng: SeedableRng::seed_from_u64(config.seed),
+            tick: 0,
+            board: Board::new(config.width, config.height),
+            cycles: BTreeMap::new(),
+            players: BTreeSet::new(),
+            config,
+        }
+    }
+
+    fn player_joined(&mut self, pid: PlayerId) {
+        self.players.insert(pid);
+    }
+
+    fn player_left(&mut self, pid: PlayerId) {
+        self.players.remove(&pid);
+        self.derez(pid);
+    }
+
+    fn current_tick(&self) -> u64 {
+        self.tick
+    }
+
+    fn tick(&mut self, inputs: &BTreeMap<PlayerId, Vec<TronInput>>) -> Vec<TronEvent> {
+        for (pid, inputs) in inputs.iter() {
+            for TronInput::Turn(dir) in inputs.iter() {
+                if let Some((_, heading)) = self.cycles.get_mut(pid) {
+                    if *dir != -*heading {
+                        *heading = *dir;
+                    }
+                }
+            }
+        }
+        let mut events = vec![];
+        for pid in self.players.clone() {
+            if !self.cycles.contains_key(&pid) && self.spawn_cycle(pid) {
+                events.push(TronEvent::Spawned(pid));
+            }
+        }
+        // every cycle moves at once, and cycles that reach the same tile both crash
+        let targets: BTreeMap<PlayerId, Coord> = self.cycles.iter().map(|(pid, (c, dir))| (*pid, self.board.step(*c, *dir))).collect();
+        let crashed: Vec<PlayerId> = targets.iter()
+            .filter(|(pid, target)| self.board.tile_or_wall(**target) != Tile::Empty || targets.iter().any(|(pid2, target2)| pid2 != *pid && target2 == *target))
+            .map(|(pid, _)| *pid)
+            .collect();
+        for pid in crashed {
+            self.derez(pid);
+            events.push(TronEvent::Crashed(pid));
+        }
+        for (pid, (c, dir)) in self.cycles.iter_mut() {
+            *c = targets[pid];
+            self.board[*c] = Tile::WormSegment { pid: *pid, dir: *dir };
+        }
+        self.tick += 1;
+        events
+    }
+}
+
+impl TronGameState {
+    /// Puts `pid` somewhere with room to move, if there's anywhere like that
+    fn spawn_cycle(&mut self, pid: PlayerId) -> bool {
+        for _ in 0..MAX_SPAWN_ATTEMPTS {
+            let c = coord(self.rng.next_u32() as usize % self.board.width, self.rng.next_u32() as usize % self.board.height);
+            let dir = Direction::from_u32(self.rng.next_u32());
+            if self.board.tile_or_wall(c) == Tile::Empty && self.board.tile_or_wall(self.board.step(c, dir)) == Tile::Empty {
+                self.board[c] = Tile::WormSegment { pid, dir };
+                self.cycles.insert(pid, (c, dir));
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Takes `pid`'s cycle and trail off of the board
+    fn derez(&mut self, pid: PlayerId) {
+        self.cycles.remove(&pid);
+        for tile in self.board.tiles.iter_mut() {
+            if let Tile::WormSegment { pid: pid2, .. } = *tile {
+                if pid == pid2 {
+                    *tile = Tile::Empty;
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_tron() {
+    let mut state = TronGameState::new(TronConfig { width: 8, height: 8, seed: 0 });
+    let (a, b) = (PlayerId(0), PlayerId(1));
+    state.player_joined(a);
+    state.player_joined(b);
+    for &(pid, c, dir) in &[(a, coord(2, 3), Direction::Right), (b, coord(4, 3), Direction::Left)] {
+        state.board[c] = Tile::WormSegment { pid, dir };
+        state.cycles.insert(pid, (c, dir));
+    }
+    let trails = |state: &TronGameState| state.board.tiles.iter().filter(|t| matches!(t, Tile::WormSegment { .. })).count();
+    assert_eq!(state.tick(&BTreeMap::new()), vec![TronEvent::Crashed(a), TronEvent::Crashed(b)]);
+    assert_eq!(trails(&state), 0);
+    assert_eq!(state.tick(&BTreeMap::new()), vec![TronEvent::Spawned(a), TronEvent::Spawned(b)]);
+    assert_eq!(trails(&state), 4);
+    state.player_left(a);
+    assert_eq!(trails(&state), 2);
+    let checksum = state.checksum();
+    state.tick(&vec![(b, vec![TronInput::Turn(Direction::Up)])].into_iter().collect());
+    assert_ne!(state.checksum(), checksum);
+}
@@ -6,168 +6,641 @@ use futures::{future, Future};
 use futures_util::{FutureExt, StreamExt, TryStreamExt};
 use futures_util::sink::SinkExt;
 use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 use tokio::sync::mpsc::{self, UnboundedSender, UnboundedReceiver};
-use tokio::time::{Duration, interval};
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::time::{Duration, Instant, interval};
 use warp::Filter;
 use warp::ws::{Ws, WebSocket, Message};
 
 pub mod common;
 use common::*;
+use common::replay::{ReplayError, ReplayRecord, ReplayWriter};
 
 macro_rules! load_asset {
-    ($name:literal) => {{
+    ($dir:expr, $name:literal) => {{
         #[cfg(feature="server-statically-pack-assets")] {
-            &include_bytes!(concat!("../", $name))[..]
+            let _ = &$dir;
+            &include_bytes!(concat!("../static/", $name))[..]
         }
         #[cfg(not(feature="server-statically-pack-assets"))] {
             use ::std::io::Read;
             let mut data = vec![];
-            let mut file = ::std::fs::File::open($name).unwrap();
+            let mut file = ::std::fs::File::open($dir.join($name)).unwrap();
             file.read_to_end(&mut data).unwrap();
             data
         }
     }}
 }
 
+/// Only snake has a browser client; the others are headless, played by bots speaking the websocket protocol
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum GameKind {
+    Snake,
+    Tron,
+}
+
+/// Read as JSON from `--config`, where any field can be left out, and then overridden by the other options
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct ServerConfig {
+    bind: IpAddr,
+    port: u16,
+    /// Ticks per second, which clients are told in `Initialize`
+    tick_rate: u32,
+    /// Where `index.html` and `pkg/` are served from, unless they're packed into the binary
+    asset_dir: PathBuf,
+    /// Players past this many are turned away, counting ones who might still reconnect
+    max_players: Option<usize>,
+    /// How many seconds a player whose connection drops has to reconnect and get their snake back
+    reconnect_grace: u64,
+    game: GameKind,
+    /// A map file to play snake on, which replaces `snake.map`
+    map: Option<PathBuf>,
+    /// Where to record each match to a replay file, if anywhere
+    record_dir: Option<PathBuf>,
+    snake: SnakeGameConfig,
+    tron: tron::TronConfig,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            bind: IpAddr::from([0, 0, 0, 0]),
+            port: 8000,
+            tick_rate: 4,
+            asset_dir: PathBuf::from("static"),
+            max_players: None,
+            reconnect_grace: 30,
+            game: GameKind::Snake,
+            map: None,
+            record_dir: None,
+            snake: SnakeGameConfig::default(),
+            tron: tron::TronConfig::default(),
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
-    let index = warp::path::end()
-        .map(|| load_asset!("static/index.html"))
+    let (mut config, print_config) = parse_args(std::env::args().skip(1).collect());
+    if print_config {
+        println!("{}", serde_json::to_string_pretty(&config).expect("server configs are always serializable"));
+        return;
+    }
+    if let Some(path) = &config.map {
+        config.snake.map = Some(load_map(path));
+    }
+    if let Err(e) = check_game_config(&config) {
+        usage(&e);
+    }
+    match config.game {
+        GameKind::Snake => serve::<SnakeGameState>(config.snake.clone(), config).await,
+        GameKind::Tron => serve::<tron::TronGameState>(config.tron.clone(), config).await,
+    }
+}
+
+/// The config, with the command line applied on top of `--config`, and whether to just print it
+fn parse_args(mut args: Vec<String>) -> (ServerConfig, bool) {
+    // the config file goes first, so that everything else on the command line overrides it
+    let mut config = match args.iter().position(|arg| arg == "--config") {
+        Some(i) => {
+            let path = args.get(i + 1).cloned().unwrap_or_else(|| usage("--config needs a path"));
+            args.drain(i..i + 2);
+            let text = std::fs::read_to_string(&path).unwrap_or_else(|e| usage(&format!("couldn't read {}: {}", path, e)));
+            serde_json::from_str(&text).unwrap_or_else(|e| usage(&format!("{}: {}", path, e)))
+        },
+        None => ServerConfig::default(),
+    };
+    let mut min_players = None;
+    let mut print_config = false;
+    let mut args = args.into_iter();
+    fn number<T: std::str::FromStr>(flag: &str, value: String) -> T {
+        value.parse().unwrap_or_else(|_| usage(&format!("{} needs a number, not {:?}", flag, value)))
+    }
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage(&format!("{} needs a value", arg)));
+        match &arg[..] {
+            "--bind" => config.bind = value().parse().unwrap_or_else(|_| usage("--bind needs an IP address")),
+            "--port" => config.port = number(&arg, value()),
+            "--tick-rate" => config.tick_rate = number(&arg, value()),
+            "--asset-dir" => config.asset_dir = PathBuf::from(value()),
+            "--max-players" => config.max_players = Some(number(&arg, value())),
+            "--reconnect-grace" => config.reconnect_grace = number(&arg, value()),
+            "--game" => config.game = match &value()[..] {
+                "snake" => GameKind::Snake,
+                "tron" => GameKind::Tron,
+                _ => usage("--game needs to be snake or tron"),
+            },
+            "--map" => config.map = Some(PathBuf::from(value())),
+            "--min-players" => min_players = Some(number(&arg, value())),
+            "--record-dir" => config.record_dir = Some(PathBuf::from(value())),
+            "--print-config" => print_config = true,
+            _ => usage(&format!("unrecognized argument {:?}", arg)),
+        }
+    }
+    if config.tick_rate == 0 {
+        usage("the tick rate needs to be at least 1");
+    }
+    if let Some(min_players) = min_players {
+        // round timings from the config file win, and are only made up if there weren't any
+        let seconds = |n: u64| n * config.tick_rate as u64;
+        config.snake.rounds.get_or_insert(RoundRules { min_players, countdown_ticks: seconds(3), time_limit: Some(seconds(3 * 60)), round_over_ticks: seconds(5) }).min_players = min_players;
+    }
+    (config, print_config)
+}
+
+/// Catches game configs that would panic or can't be played, before any room is opened with them
+fn check_game_config(config: &ServerConfig) -> Result<(), String> {
+    let players = config.max_players.unwrap_or(1).max(1);
+    match config.game {
+        GameKind::Snake => {
+            let snake = &config.snake;
+            if snake.map.is_none() && (snake.width < 3 || snake.height < 3) {
+                return Err(format!("snake boards need to be at least 3x3, not {}x{}", snake.width, snake.height));
+            }
+            let state = SnakeGameState::new(snake.clone());
+            state.validate().map_err(|e| format!("the snake config doesn't make a valid board: {:?}", e))?;
+            let open = state.board.coords_where(|tile| *tile != Tile::Wall).count() as u64;
+            let needed = players as u64 * (snake.starting_length as u64 + snake.food_per_player) + snake.food_base + snake.powerup_count;
+            if open < needed {
+                return Err(format!("the snake board has {} open tiles, but {} player(s) with their food and power-ups need {}", open, players, needed));
+            }
+        },
+        GameKind::Tron => {
+            let tron = &config.tron;
+            if tron.width < 3 || tron.height < 3 {
+                return Err(format!("tron boards need to be at least 3x3, not {}x{}", tron.width, tron.height));
+            }
+            let open = (tron.width - 2) * (tron.height - 2);
+            if open < players {
+                return Err(format!("the tron board has {} open tiles, but {} players need one each", open, players));
+            }
+        },
+    }
+    Ok(())
+}
+
+async fn serve<G>(game_config: G::Config, config: ServerConfig) where G: 'static+Send+GameState, G::PlayerInput: Send, G::Config: Send {
+    // the client would fail to decode any other game's messages
+    let serves_client = config.game == GameKind::Snake;
+    let client = warp::any().and_then(move || future::ready(if serves_client { Ok(()) } else { Err(warp::reject::not_found()) })).untuple_one();
+
+    let asset_dir = config.asset_dir.clone();
+    let index = client.and(warp::path::end())
+        .map(move || load_asset!(asset_dir, "index.html"))
         .with(warp::reply::with::header("Content-type", "text/html"));
 
-    let wasm_snake_js = warp::path!("pkg" / "wasm_snake.js")
-        .map(|| load_asset!("static/pkg/wasm_snake.js"))
+    let game = config.game;
+    let headless_index = warp::path::end()
+        .map(move || format!("This server runs {:?}, which has no browser client; connect to /client_connection with a bot instead\n", game));
+
+    let wasm_snake_js = client.and(warp::path!("pkg" / "wasm_snake.js"))
+        .map({ let asset_dir = config.asset_dir.clone(); move || load_asset!(asset_dir, "pkg/wasm_snake.js") })
         .with(warp::reply::with::header("Content-type", "text/javascript"));
 
-    let wasm_snake_wasm = warp::path!("pkg" / "wasm_snake_bg.wasm")
-        .map(|| load_asset!("static/pkg/wasm_snake_bg.wasm"))
+    let wasm_snake_wasm = client.and(warp::path!("pkg" / "wasm_snake_bg.wasm"))
+        .map({ let asset_dir = config.asset_dir.clone(); move || load_asset!(asset_dir, "pkg/wasm_snake_bg.wasm") })
         .with(warp::reply::with::header("Content-type", "application/wasm"));
 
-    let (server_tx, server_rx) = mpsc::unbounded_channel();
-    let server_tx_ = server_tx.clone();
-    let ws_endpoint = warp::path("client_connection")
-        .and(warp::ws())
-        .map(move |ws: Ws| { let tmp = server_tx_.clone(); ws.on_upgrade(move |websocket| handle_client_connection(tmp.clone(), websocket)) });
-
+    let (lobby_tx, lobby_rx) = mpsc::unbounded_channel();
     tokio::task::spawn({
-        let mut server_state = ServerGameState::new();
-        server_rx.for_each(move |msg| server_state.handle_msg(msg))
+        let mut lobby = Lobby::<G> { game_config, config: config.clone(), lobby_tx: lobby_tx.clone(), rooms: BTreeMap::new() };
+        lobby_rx.for_each(move |msg| lobby.handle_msg(msg))
     });
 
-    let server_tx_ = server_tx.clone();
-    tokio::task::spawn(interval(Duration::from_millis(250)).for_each(move |_| { let _ = server_tx_.send(ServerInternalMsg::DoTick); future::ready(()) }));
+    let ws_endpoint = connection_endpoint("client_connection", Role::Player, lobby_tx.clone());
+    let spectate_endpoint = connection_endpoint("spectate", Role::Spectator, lobby_tx.clone());
+
+    let rooms_endpoint = warp::path!("rooms")
+        .and_then({
+            async fn tmp<G: GameState>(lobby_tx: UnboundedSender<LobbyMsg<G>>) -> Result<warp::reply::Json, warp::Rejection> {
+                let (tx, mut rx) = mpsc::unbounded_channel();
+                lobby_tx.send(LobbyMsg::ListRooms(tx)).map_err(|_| warp::reject::not_found())?;
+                Ok(warp::reply::json(&rx.recv().await.unwrap_or_default()))
+            }
+            let lobby_tx = lobby_tx.clone();
+            move || tmp(lobby_tx.clone())
+        });
 
     let state_endpoint = warp::path("state")
+        .and(room_name())
         .and_then({
-            async fn tmp(server_tx: UnboundedSender<ServerInternalMsg<SnakeGameState>>) -> Result<String, warp::Rejection> {
+            async fn tmp<G: GameState>(room: String, lobby_tx: UnboundedSender<LobbyMsg<G>>) -> Result<String, warp::Rejection> {
                 let (tx, mut rx) = mpsc::unbounded_channel();
-                Ok(match server_tx.send(ServerInternalMsg::GetCurrentState(tx)) {
+                Ok(match lobby_tx.send(LobbyMsg::GetCurrentState(room, tx)) {
                     Ok(()) => match rx.recv().await {
                         Some(state) => state,
-                        None => format!("recv() failed"),
+                        None => "no such room".to_string(),
                     }
                     Err(e) => format!("send() failed: {:?}", e),
                 })
             }
-            move || { let server_tx_ = server_tx.clone(); tmp(server_tx_.clone()) }
+            move |room| tmp(room, lobby_tx.clone())
         })
         .with(warp::reply::with::header("Content-type", "text/plain"));
 
     let server = index
+        .or(headless_index)
         .or(wasm_snake_js)
         .or(wasm_snake_wasm)
         .or(ws_endpoint)
+        .or(spectate_endpoint)
+        .or(rooms_endpoint)
         .or(state_endpoint);
 
-    let into_ip = ([0, 0, 0, 0], 8000);
-    println!("Serving on {:?}", into_ip);
-    warp::serve(server).run(into_ip).await;
+    println!("Serving on {}:{}", config.bind, config.port);
+    warp::serve(server).run((config.bind, config.port)).await;
+}
+
+fn usage(error: &str) -> ! {
+    eprintln!("{}", error);
+    eprintln!("usage: server [--config FILE] [--bind IP] [--port N] [--tick-rate N] [--asset-dir DIR] [--max-players N]");
+    eprintln!("              [--reconnect-grace SECONDS] [--game snake|tron] [--map PATH] [--min-players N] [--record-dir DIR]");
+    eprintln!("              [--print-config]");
+    std::process::exit(1);
+}
+
+fn load_map(path: &Path) -> Board {
+    let text = std::fs::read_to_string(path).unwrap_or_else(|e| usage(&format!("couldn't read {}: {}", path.display(), e)));
+    Board::parse_map(&text).unwrap_or_else(|e| usage(&format!("{}: {}", path.display(), e)))
+}
+
+/// Where clients that don't ask for a room go
+const DEFAULT_ROOM: &str = "default";
+
+/// A room name as the last segment of a path, or `DEFAULT_ROOM` if there isn't one; names are short and URL-safe, so
+/// that they don't need escaping
+fn room_name() -> impl Filter<Extract=(String,), Error=warp::Rejection> + Copy {
+    let named = warp::path::param::<String>()
+        .and(warp::path::end())
+        .and_then(|room: String| future::ready(if !room.is_empty() && room.len() <= 32 && room.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            Ok(room)
+        } else {
+            Err(warp::reject::not_found())
+        }));
+    warp::path::end().map(|| DEFAULT_ROOM.to_string()).or(named).unify()
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct ConnectionQuery {
+    session: Option<u64>,
+}
+
+/// `/path` and `/path/{room}`, which connect a websocket to a room as `role`; players can add `?session=TOKEN` to
+/// reclaim the snake they had before their connection dropped
+fn connection_endpoint<G>(path: &'static str, role: Role, lobby_tx: UnboundedSender<LobbyMsg<G>>) -> impl Filter<Extract=(impl warp::Reply,), Error=warp::Rejection> + Clone
+where G: 'static+Send+GameState, G::PlayerInput: Send {
+    warp::path(path)
+        .and(room_name())
+        .and(warp::query::<ConnectionQuery>())
+        .and(warp::ws())
+        .map(move |room: String, query: ConnectionQuery, ws: Ws| {
+            let (tmp, session) = (lobby_tx.clone(), query.session.map(SessionToken));
+            ws.on_upgrade(move |websocket| handle_client_connection(tmp, room, role, session, websocket))
+        })
+}
+
+/// Keeps a misbehaving client from bloating every `DoTick`
+const MAX_INPUTS_PER_TICK: usize = 8;
+/// How often, in ticks, clients are sent a checksum to check their world against
+const CHECKSUM_INTERVAL: u64 = 16;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Role {
+    Player,
+    /// Gets everything a player does, but has no snake and can't make inputs
+    Spectator,
+}
+
+#[derive(Debug)]
+enum LobbyMsg<G: GameState> {
+    Connected(String, Role, Option<SessionToken>, UnboundedSender<ServerToClient<G>>, UnboundedReceiver<ClientToServer<G>>),
+    /// Sent by a room whenever it drops a connection
+    Left(String, Role),
+    ListRooms(UnboundedSender<Vec<RoomInfo>>),
+    GetCurrentState(String, UnboundedSender<String>),
+}
+
+#[derive(Serialize, Clone, Debug)]
+struct RoomInfo {
+    name: String,
+    players: usize,
+    spectators: usize,
+}
+
+/// A room's channel and how many of each kind of connection it has, counting ones on their way in and players who might
+/// still reconnect
+struct RoomHandle<G: GameState> {
+    tx: UnboundedSender<ServerInternalMsg<G>>,
+    players: usize,
+    spectators: usize,
+}
+
+/// Owns the rooms, starting each one when its first connection arrives and closing it when its last one leaves
+struct Lobby<G: GameState> {
+    game_config: G::Config,
+    config: ServerConfig,
+    lobby_tx: UnboundedSender<LobbyMsg<G>>,
+    rooms: BTreeMap<String, RoomHandle<G>>,
+}
+
+impl<G> Lobby<G> where G: 'static+Send+GameState, G::PlayerInput: Send, G::Config: Send {
+    fn handle_msg(&mut self, msg: LobbyMsg<G>) -> impl Future<Output=()> {
+        use LobbyMsg::*;
+        match msg {
+            Connected(room, role, session, tx, rx) => {
+                // rooms decide whether they're full, since only they know who's reconnecting
+                let (game_config, config, lobby_tx) = (&self.game_config, &self.config, &self.lobby_tx);
+                let handle = self.rooms.entry(room.clone()).or_insert_with(|| {
+                    println!("Lobby::handle_msg: opening {:?}", room);
+                    RoomHandle { tx: spawn_room(room.clone(), game_config.clone(), config, lobby_tx.clone()), players: 0, spectators: 0 }
+                });
+                let _ = match role {
+                    Role::Player => { handle.players += 1; handle.tx.send(ServerInternalMsg::PlayerConnected(session, tx, rx)) },
+                    Role::Spectator => { handle.spectators += 1; handle.tx.send(ServerInternalMsg::SpectatorConnected(tx, rx)) },
+                };
+            },
+            Left(room, role) => {
+                if let Some(handle) = self.rooms.get_mut(&room) {
+                    match role {
+                        Role::Player => handle.players = handle.players.saturating_sub(1),
+                        Role::Spectator => handle.spectators = handle.spectators.saturating_sub(1),
+                    }
+                    if handle.players + handle.spectators == 0 {
+                        println!("Lobby::handle_msg: closing {:?}", room);
+                        let _ = handle.tx.send(ServerInternalMsg::Close);
+                        self.rooms.remove(&room);
+                    }
+                }
+            },
+            ListRooms(tx) => {
+                let _ = tx.send(self.rooms.iter().map(|(name, handle)| RoomInfo { name: name.clone(), players: handle.players, spectators: handle.spectators }).collect());
+            },
+            GetCurrentState(room, tx) => {
+                // if there's no such room, dropping `tx` says so
+                if let Some(handle) = self.rooms.get(&room) {
+                    let _ = handle.tx.send(ServerInternalMsg::GetCurrentState(tx));
+                }
+            },
+        }
+        future::ready(())
+    }
+}
+
+/// Starts a room's game and its tick timer, which both stop once it's sent `ServerInternalMsg::Close`
+fn spawn_room<G>(name: String, game_config: G::Config, config: &ServerConfig, lobby_tx: UnboundedSender<LobbyMsg<G>>) -> UnboundedSender<ServerInternalMsg<G>> where G: 'static+Send+GameState, G::PlayerInput: Send {
+    let (room_tx, room_rx) = mpsc::unbounded_channel();
+    let mut room = ServerGameState::<G>::new(name, game_config, config, lobby_tx);
+    tokio::task::spawn(room_rx.take_while(|msg| future::ready(!matches!(msg, ServerInternalMsg::Close))).for_each(move |msg| room.handle_msg(msg)));
+    let timer_tx = room_tx.clone();
+    tokio::task::spawn(interval(Duration::from_secs(1) / config.tick_rate).take_while(move |_| future::ready(timer_tx.send(ServerInternalMsg::DoTick).is_ok())).for_each(|_| future::ready(())));
+    room_tx
 }
 
 #[derive(Debug)]
 enum ServerInternalMsg<G: GameState> {
-    PlayerConnected(UnboundedSender<G::S2CMsg>, UnboundedReceiver<G::C2SMsg>),
+    PlayerConnected(Option<SessionToken>, UnboundedSender<ServerToClient<G>>, UnboundedReceiver<ClientToServer<G>>),
+    SpectatorConnected(UnboundedSender<ServerToClient<G>>, UnboundedReceiver<ClientToServer<G>>),
     GetCurrentState(UnboundedSender<String>),
     DoTick,
+    /// The room is empty, and is being closed
+    Close,
 }
 
+/// A client's outgoing and incoming messages
+type Connection<G> = (UnboundedSender<ServerToClient<G>>, UnboundedReceiver<ClientToServer<G>>);
+
 #[derive(Debug)]
 struct ServerGameState<G: GameState> {
+    room: String,
     next_pid: PlayerId,
     game_state: G,
-    channels: BTreeMap<PlayerId, (UnboundedSender<G::S2CMsg>, UnboundedReceiver<G::C2SMsg>)>,
-    player_inputs: BTreeMap<PlayerId, G::PlayerInput>,
+    channels: BTreeMap<PlayerId, Connection<G>>,
+    player_inputs: BTreeMap<PlayerId, Vec<G::PlayerInput>>,
+    /// Every player's token, including detached ones'
+    sessions: BTreeMap<PlayerId, SessionToken>,
+    /// Players whose connections dropped, and when they stop being able to reconnect
+    detached: BTreeMap<PlayerId, Instant>,
+    spectators: Vec<Connection<G>>,
+    replay: Option<ReplayWriter<BufWriter<File>>>,
+    tick_rate: u32,
+    max_players: Option<usize>,
+    reconnect_grace: Duration,
+    lobby_tx: UnboundedSender<LobbyMsg<G>>,
 }
 
-impl ServerGameState<SnakeGameState> {
-    fn new() -> ServerGameState<SnakeGameState> {
+impl<G: GameState> ServerGameState<G> {
+    fn new(room: String, game_config: G::Config, config: &ServerConfig, lobby_tx: UnboundedSender<LobbyMsg<G>>) -> ServerGameState<G> {
+        let game_state = G::new(game_config);
+        let replay = config.record_dir.as_deref().and_then(|dir| match start_replay(dir, &room, &game_state) {
+            Ok((path, replay)) => { println!("Recording to {}", path.display()); Some(replay) },
+            Err(e) => { eprintln!("Not recording a replay: {}", e); None },
+        });
         ServerGameState {
+            room,
             next_pid: PlayerId(0),
-            game_state: SnakeGameState::new(),
+            game_state,
             channels: BTreeMap::new(),
             player_inputs: BTreeMap::new(),
+            sessions: BTreeMap::new(),
+            detached: BTreeMap::new(),
+            spectators: vec![],
+            replay,
+            tick_rate: config.tick_rate,
+            max_players: config.max_players,
+            reconnect_grace: Duration::from_secs(config.reconnect_grace),
+            lobby_tx,
         }
     }
-    fn handle_msg(&mut self, msg: ServerInternalMsg<SnakeGameState>) -> impl Future<Output=()> {
+    /// Call with everything that's done to `game_state`, in the order it's done
+    fn record(&mut self, record: ReplayRecord<G>) {
+        if let Some(replay) = &mut self.replay {
+            if let Err(e) = replay.record(&record).and_then(|()| replay.get_mut().flush().map_err(ReplayError::Io)) {
+                eprintln!("Stopped recording the replay: {}", e);
+                self.replay = None;
+            }
+        }
+    }
+    /// Sends `msg` to every spectator, letting the lobby know about any that have gone away
+    fn send_to_spectators(&mut self, msg: &ServerToClient<G>) {
+        let (room, lobby_tx) = (&self.room, &self.lobby_tx);
+        self.spectators.retain(|(tx, _)| tx.send(msg.clone()).is_ok() || {
+            let _ = lobby_tx.send(LobbyMsg::Left(room.clone(), Role::Spectator));
+            false
+        });
+    }
+    /// Sends `msg` to every player and spectator; players who've gone away are noticed on the next `DoTick`
+    fn broadcast(&mut self, msg: ServerToClient<G>) {
+        for (_, (tx, _)) in self.channels.iter_mut() {
+            let _ = tx.send(msg.clone());
+        }
+        self.send_to_spectators(&msg);
+    }
+    fn initialize(&self, pid: PlayerId) -> ServerToClient<G> {
+        ServerToClient::Initialize { pid: Some(pid), world: self.game_state.snapshot(), ticks_per_second: self.tick_rate, session: self.sessions.get(&pid).cloned() }
+    }
+    /// Takes `pid` out of the game for good
+    fn drop_player(&mut self, pid: PlayerId) {
+        self.game_state.player_left(pid);
+        self.record(ReplayRecord::PlayerLeft(pid));
+        self.sessions.remove(&pid);
+        let _ = self.lobby_tx.send(LobbyMsg::Left(self.room.clone(), Role::Player));
+        self.broadcast(ServerToClient::PlayerDisconnected { pid });
+    }
+    fn handle_msg(&mut self, msg: ServerInternalMsg<G>) -> impl Future<Output=()> {
         use ServerInternalMsg::*;
         let mut to_remove = vec![];
-        let mut send_with_cleanup = |pid, tx: &UnboundedSender<ServerToClient>, msg| {
+        let mut send_with_cleanup = |pid, tx: &UnboundedSender<ServerToClient<G>>, msg| {
             if let Err(_) = tx.send(msg) {
                 to_remove.push(pid);
             }
         };
         match msg {
-            PlayerConnected(tx, rx) => {
+            PlayerConnected(session, tx, rx) => {
+                if let Some(pid) = session.and_then(|session| self.sessions.iter().find(|(_, s)| **s == session).map(|(pid, _)| *pid)) {
+                    println!("ServerGameState::handle_msg: {:?} reconnected in {:?}", pid, self.room);
+                    if self.detached.remove(&pid).is_some() {
+                        self.game_state.player_reattached(pid);
+                        self.record(ReplayRecord::PlayerReattached(pid));
+                        self.broadcast(ServerToClient::PlayerReattached { pid });
+                    }
+                    // otherwise the old connection hasn't been noticed to be gone yet, and this one just replaces it
+                    // either way, the lobby counted this as a new player
+                    let _ = self.lobby_tx.send(LobbyMsg::Left(self.room.clone(), Role::Player));
+                    send_with_cleanup(pid, &tx, self.initialize(pid));
+                    self.channels.insert(pid, (tx, rx));
+                    self.cleanup(to_remove);
+                    return future::ready(());
+                }
+                if self.max_players.is_some_and(|max| self.sessions.len() >= max) {
+                    // dropping `tx` closes the websocket
+                    println!("ServerGameState::handle_msg: turning a player away, {:?} is full", self.room);
+                    let _ = self.lobby_tx.send(LobbyMsg::Left(self.room.clone(), Role::Player));
+                    return future::ready(());
+                }
                 let pid = self.next_pid;
                 self.next_pid.0 += 1;
-                println!("ServerGameState::handle_msg: PlayerConnected {:?}", pid);
-                self.game_state.spawn_player(pid);
-                send_with_cleanup(pid, &tx, ServerToClient::Initialize { pid, world: self.game_state.clone() });
-                for (pid, (tx, _)) in self.channels.iter_mut() {
-                    // TODO: lighter-weight way of notifying of new players
-                    send_with_cleanup(*pid, &tx, ServerToClient::Initialize { pid: *pid, world: self.game_state.clone() });
+                println!("ServerGameState::handle_msg: PlayerConnected {:?} in {:?}", pid, self.room);
+                self.sessions.insert(pid, SessionToken(rand::random()));
+                self.game_state.player_joined(pid);
+                self.record(ReplayRecord::PlayerJoined(pid));
+                send_with_cleanup(pid, &tx, self.initialize(pid));
+                for (pid2, (tx, _)) in self.channels.iter_mut() {
+                    // everyone else replays the join on their own copy of the world
+                    send_with_cleanup(*pid2, tx, ServerToClient::PlayerJoined { pid });
                 }
+                self.send_to_spectators(&ServerToClient::PlayerJoined { pid });
                 self.channels.insert(pid, (tx, rx));
             }
+            SpectatorConnected(tx, rx) => {
+                println!("ServerGameState::handle_msg: SpectatorConnected in {:?}", self.room);
+                // if this fails, the next message to spectators lets the lobby know
+                let _ = tx.send(ServerToClient::Initialize { pid: None, world: self.game_state.snapshot(), ticks_per_second: self.tick_rate, session: None });
+                self.spectators.push((tx, rx));
+            }
             GetCurrentState(tx) => {
                 let _ = tx.send(format!("{:?}", self));
             }
+            Close => {},
             DoTick => {
+                // spectators are checked even when nothing's ticking, so that the last one leaving closes the room
+                let (room, lobby_tx, game_state, tick_rate) = (&self.room, &self.lobby_tx, &self.game_state, self.tick_rate);
+                self.spectators.retain_mut(|(tx, rx)| loop {
+                    match rx.try_recv() {
+                        // spectators have no inputs to make, but can fall out of sync like anyone else
+                        Ok(ClientToServer::RequestResync) => { let _ = tx.send(ServerToClient::Initialize { pid: None, world: game_state.snapshot(), ticks_per_second: tick_rate, session: None }); },
+                        Ok(ClientToServer::InputAtTick { .. }) => {},
+                        Err(TryRecvError::Empty) => break true,
+                        Err(TryRecvError::Closed) => { let _ = lobby_tx.send(LobbyMsg::Left(room.clone(), Role::Spectator)); break false },
+                    }
+                });
+                let now = Instant::now();
+                let expired: Vec<PlayerId> = self.detached.iter().filter(|(_, deadline)| **deadline <= now).map(|(pid, _)| *pid).collect();
+                for pid in expired {
+                    println!("ServerGameState::handle_msg: {:?} didn't come back to {:?}", pid, self.room);
+                    self.detached.remove(&pid);
+                    self.drop_player(pid);
+                }
                 if self.channels.len() > 0 {
-                    for (pid, (_, rx)) in self.channels.iter_mut() {
+                    let (game_state, sessions, tick_rate) = (&self.game_state, &self.sessions, self.tick_rate);
+                    for (pid, (tx, rx)) in self.channels.iter_mut() {
                         while let Ok(c2s) = rx.try_recv() {
                             use ClientToServer::*;
                             match c2s {
                                 InputAtTick { tick, input } => {
                                     // TODO: rollback and replay world or discard input based on how recent it is, and send a sparser response
-                                    self.player_inputs.insert(*pid, input);
+                                    let inputs = self.player_inputs.entry(*pid).or_default();
+                                    if inputs.len() < MAX_INPUTS_PER_TICK {
+                                        inputs.push(input);
+                                    }
                                     //let _ = tx.send(ServerToClient::Initialize { pid: *pid, world: self.game_state.clone() });
                                 },
+                                RequestResync => {
+                                    println!("ServerGameState::handle_msg: resyncing {:?}", pid);
+                                    send_with_cleanup(*pid, tx, ServerToClient::Initialize { pid: Some(*pid), world: game_state.snapshot(), ticks_per_second: tick_rate, session: sessions.get(pid).cloned() });
+                                },
                             }
                         }
                     }
+                    let tick = self.game_state.current_tick();
+                    let send_checksum = tick.is_multiple_of(CHECKSUM_INTERVAL);
+                    // replays check every tick
+                    let checksum = if send_checksum || self.replay.is_some() { self.game_state.checksum() } else { 0 };
+                    let msg = ServerToClient::DoTick { tick, inputs: self.player_inputs.clone(), checksum: Some(checksum).filter(|_| send_checksum) };
                     for (pid, (tx, _)) in self.channels.iter_mut() {
-                        send_with_cleanup(*pid, &tx, ServerToClient::DoTick { tick: self.game_state.tick, inputs: self.player_inputs.clone() });
+                        send_with_cleanup(*pid, &tx, msg.clone());
+                    }
+                    self.send_to_spectators(&msg);
+                    if self.replay.is_some() {
+                        self.record(ReplayRecord::Tick { tick, inputs: self.player_inputs.clone(), checksum });
                     }
                     self.game_state.tick(&self.player_inputs);
+                    // inputs like `Respawn` only apply once
+                    self.player_inputs.clear();
                     //println!("current tick: {}", self.game_state.tick);
                 }
             },
         }
+        self.cleanup(to_remove);
+        future::ready(())
+    }
+    /// Detaches players whose connections have dropped, or drops them outright if there's no grace period
+    fn cleanup(&mut self, to_remove: Vec<PlayerId>) {
         for pid in to_remove {
-            self.game_state.remove_player(pid, 0);
             self.player_inputs.remove(&pid);
-            self.channels.remove(&pid);
-            for (_, (tx, _)) in self.channels.iter_mut() {
-                // if this send fails, so well the next DoTick, so they'll eventually get pruned
-                let _ = tx.send(ServerToClient::PlayerDisconnected { pid });
+            if self.channels.remove(&pid).is_none() {
+                continue
+            }
+            if self.reconnect_grace == Duration::from_secs(0) {
+                self.drop_player(pid);
+                continue
             }
+            println!("ServerGameState::handle_msg: {:?} detached from {:?}", pid, self.room);
+            // the lobby still counts them, which keeps the room open while they might come back
+            self.detached.insert(pid, Instant::now() + self.reconnect_grace);
+            self.game_state.player_detached(pid);
+            self.record(ReplayRecord::PlayerDetached(pid));
+            self.broadcast(ServerToClient::PlayerDetached { pid });
         }
-        future::ready(())
     }
 }
 
-async fn handle_client_connection<G: GameState>(server_tx: UnboundedSender<ServerInternalMsg<G>>, websocket: WebSocket) where G::S2CMsg: 'static+Send, G::C2SMsg: 'static+Send {
+/// Starts `dir/<room>-<unix time>.replay`, recording from `initial`
+fn start_replay<G: GameState>(dir: &Path, room: &str, initial: &G) -> Result<(PathBuf, ReplayWriter<BufWriter<File>>), ReplayError> {
+    std::fs::create_dir_all(dir).map_err(ReplayError::Io)?;
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let path = dir.join(format!("{}-{}.replay", room, now));
+    let mut replay = ReplayWriter::new(BufWriter::new(File::create(&path).map_err(ReplayError::Io)?), initial)?;
+    replay.get_mut().flush().map_err(ReplayError::Io)?;
+    Ok((path, replay))
+}
+
+async fn handle_client_connection<G>(lobby_tx: UnboundedSender<LobbyMsg<G>>, room: String, role: Role, session: Option<SessionToken>, websocket: WebSocket) where G: 'static+Send+GameState, G::PlayerInput: Send {
     let (ws_tx, ws_rx) = websocket.split();
     let (s2c_tx, s2c_rx) = mpsc::unbounded_channel();
     let (c2s_tx, c2s_rx) = mpsc::unbounded_channel();
@@ -177,11 +650,158 @@ async fn handle_client_connection<G: GameState>(server_tx: UnboundedSender<Serve
     })).forward(ws_tx));
     tokio::task::spawn(ws_rx.filter_map(|x| future::ready(x.ok())).filter_map(|x| future::ready(bincode::deserialize(x.as_bytes()).ok()))
         //.forward(c2s_tx)
-        .for_each(move |x: G::C2SMsg| {
+        .for_each(move |x: ClientToServer<G>| {
             //println!("Got c2s: {:?}", x);
             let _ = c2s_tx.send(x);
             future::ready(())
         })
     );
-    let _ = server_tx.send(ServerInternalMsg::PlayerConnected(s2c_tx, c2s_rx));
+    let _ = lobby_tx.send(LobbyMsg::Connected(room, role, session, s2c_tx, c2s_rx));
+}
+
+#[test]
+fn test_state_hides_sessions() {
+    let (lobby_tx, _lobby_rx) = mpsc::unbounded_channel();
+    let mut state = ServerGameState::<SnakeGameState>::new(DEFAULT_ROOM.to_string(), SnakeGameConfig::default(), &ServerConfig::default(), lobby_tx);
+    let ((s2c_tx, mut s2c_rx), (_c2s_tx, c2s_rx)) = (mpsc::unbounded_channel(), mpsc::unbounded_channel());
+    futures::executor::block_on(state.handle_msg(ServerInternalMsg::PlayerConnected(None, s2c_tx, c2s_rx)));
+    let session = match s2c_rx.try_recv() {
+        Ok(ServerToClient::Initialize { session: Some(session), .. }) => session,
+        x => panic!("expected an Initialize with a session, got {:?}", x),
+    };
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    futures::executor::block_on(state.handle_msg(ServerInternalMsg::GetCurrentState(tx)));
+    let dump = rx.try_recv().unwrap();
+    assert!(dump.contains("sessions") && !dump.contains(&session.0.to_string()), "{}", dump);
+}
+
+#[test]
+fn test_tron_room() {
+    use tron::{TronConfig, TronGameState, TronInput};
+    let (lobby_tx, _lobby_rx) = mpsc::unbounded_channel();
+    let config = ServerConfig { game: GameKind::Tron, reconnect_grace: 0, ..ServerConfig::default() };
+    let mut state = ServerGameState::<TronGameState>::new(DEFAULT_ROOM.to_string(), TronConfig::default(), &config, lobby_tx);
+    let ((a_tx, mut a_rx), (a_c2s, a_c2s_rx)) = (mpsc::unbounded_channel(), mpsc::unbounded_channel());
+    let ((b_tx, mut b_rx), (b_c2s, b_c2s_rx)) = (mpsc::unbounded_channel(), mpsc::unbounded_channel());
+    futures::executor::block_on(state.handle_msg(ServerInternalMsg::PlayerConnected(None, a_tx, a_c2s_rx)));
+    futures::executor::block_on(state.handle_msg(ServerInternalMsg::PlayerConnected(None, b_tx, b_c2s_rx)));
+    let (a, b) = (PlayerId(0), PlayerId(1));
+    assert!(matches!(a_rx.try_recv(), Ok(ServerToClient::Initialize { pid: Some(pid), .. }) if pid == a));
+    assert!(matches!(a_rx.try_recv(), Ok(ServerToClient::PlayerJoined { pid }) if pid == b));
+    assert!(matches!(b_rx.try_recv(), Ok(ServerToClient::Initialize { pid: Some(pid), .. }) if pid == b));
+
+    a_c2s.send(ClientToServer::InputAtTick { tick: 0, input: TronInput::Turn(Direction::Up) }).unwrap();
+    futures::executor::block_on(state.handle_msg(ServerInternalMsg::DoTick));
+    for rx in [&mut a_rx, &mut b_rx].iter_mut() {
+        match rx.try_recv() {
+            Ok(ServerToClient::DoTick { tick: 0, inputs, .. }) => assert_eq!(inputs, vec![(a, vec![TronInput::Turn(Direction::Up)])].into_iter().collect()),
+            x => panic!("expected the first tick, got {:?}", x),
+        }
+    }
+    assert_eq!(state.game_state.cycles.keys().cloned().collect::<Vec<_>>(), vec![a, b]);
+
+    // b's connection dropping is noticed when its next tick can't be sent
+    drop((b_rx, b_c2s));
+    futures::executor::block_on(state.handle_msg(ServerInternalMsg::DoTick));
+    assert!(matches!(a_rx.try_recv(), Ok(ServerToClient::DoTick { tick: 1, .. })));
+    assert!(matches!(a_rx.try_recv(), Ok(ServerToClient::PlayerDisconnected { pid }) if pid == b));
+    assert_eq!(state.game_state.players.iter().cloned().collect::<Vec<_>>(), vec![a]);
+    assert!(!state.game_state.cycles.contains_key(&b));
+}
+
+#[test]
+fn test_parse_args() {
+    let args = |line: &str| line.split_whitespace().map(String::from).collect::<Vec<_>>();
+    let (config, print_config) = parse_args(args("--port 9000 --game tron --max-players 3 --print-config"));
+    assert_eq!((config.port, config.game, config.max_players, print_config), (9000, GameKind::Tron, Some(3), true));
+
+    // flags override the config file wherever it comes, and --min-players keeps its round timings
+    let path = std::env::temp_dir().join(format!("wasm-snake-test-config-{}.json", std::process::id()));
+    std::fs::write(&path, r#"{"port": 7000, "tick_rate": 10, "snake": {"width": 20, "rounds": {"min_players": 4, "countdown_ticks": 1, "time_limit": null, "round_over_ticks": 2}}}"#).unwrap();
+    let (config, _) = parse_args(args(&format!("--port 7001 --min-players 2 --config {}", path.display())));
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!((config.port, config.tick_rate, config.snake.width, config.snake.height), (7001, 10, 20, 30));
+    assert_eq!(config.snake.rounds, Some(RoundRules { min_players: 2, countdown_ticks: 1, time_limit: None, round_over_ticks: 2 }));
+    // without any in the config, they're made up from the tick rate
+    let (config, _) = parse_args(args("--tick-rate 10 --min-players 2"));
+    assert_eq!(config.snake.rounds, Some(RoundRules { min_players: 2, countdown_ticks: 30, time_limit: Some(1800), round_over_ticks: 50 }));
+
+    assert!(serde_json::from_str::<ServerConfig>(r#"{"snake": {"widht": 20}}"#).is_err());
+    assert!(serde_json::from_str::<ServerConfig>(r#"{"snake": {"rounds": {"min_players": 2, "countdown": 3}}}"#).is_err());
+    assert!(serde_json::from_str::<ServerConfig>(r#"{"tron": {"sed": 1}}"#).is_err());
+}
+
+#[test]
+fn test_check_game_config() {
+    assert_eq!(check_game_config(&ServerConfig::default()), Ok(()));
+    let snake = |snake: SnakeGameConfig, max_players| check_game_config(&ServerConfig { snake, max_players, ..ServerConfig::default() });
+    assert!(snake(SnakeGameConfig { width: 0, height: 0, ..SnakeGameConfig::default() }, None).is_err());
+    assert!(snake(SnakeGameConfig { width: 3, height: 3, ..SnakeGameConfig::default() }, None).is_err());
+    assert_eq!(snake(SnakeGameConfig { width: 3, height: 3, food_per_player: 0, food_base: 0, powerup_count: 0, ..SnakeGameConfig::default() }, None), Ok(()));
+    assert!(snake(SnakeGameConfig { width: 6, height: 6, ..SnakeGameConfig::default() }, Some(20)).is_err());
+    let tron = |tron: tron::TronConfig, max_players| check_game_config(&ServerConfig { game: GameKind::Tron, tron, max_players, ..ServerConfig::default() });
+    assert!(tron(tron::TronConfig { width: 0, height: 0, ..tron::TronConfig::default() }, None).is_err());
+    assert!(tron(tron::TronConfig { width: 3, height: 3, ..tron::TronConfig::default() }, Some(2)).is_err());
+    assert_eq!(tron(tron::TronConfig { width: 3, height: 3, ..tron::TronConfig::default() }, Some(1)), Ok(()));
+}
+
+#[cfg(test)]
+fn list_rooms<G>(lobby: &mut Lobby<G>) -> Vec<(String, usize, usize)> where G: 'static+Send+GameState, G::PlayerInput: Send, G::Config: Send {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    futures::executor::block_on(lobby.handle_msg(LobbyMsg::ListRooms(tx)));
+    rx.try_recv().unwrap().into_iter().map(|room| (room.name, room.players, room.spectators)).collect()
+}
+
+#[tokio::test]
+async fn test_lobby_rooms() {
+    let (lobby_tx, _lobby_rx) = mpsc::unbounded_channel();
+    let mut lobby = Lobby::<SnakeGameState> { game_config: SnakeGameConfig::default(), config: ServerConfig::default(), lobby_tx, rooms: BTreeMap::new() };
+    let mut connect = |room: &str, role| {
+        let ((s2c_tx, s2c_rx), (_c2s_tx, c2s_rx)) = (mpsc::unbounded_channel(), mpsc::unbounded_channel());
+        futures::executor::block_on(lobby.handle_msg(LobbyMsg::Connected(room.to_string(), role, None, s2c_tx, c2s_rx)));
+        s2c_rx
+    };
+    let mut player = connect("a", Role::Player);
+    let _spectator = connect("a", Role::Spectator);
+    let _other = connect("b", Role::Player);
+    // the room is running, and welcomes its first player
+    assert!(matches!(player.recv().await, Some(ServerToClient::Initialize { pid: Some(PlayerId(0)), .. })));
+    assert_eq!(list_rooms(&mut lobby), vec![("a".to_string(), 1, 1), ("b".to_string(), 1, 0)]);
+
+    futures::executor::block_on(lobby.handle_msg(LobbyMsg::Left("b".to_string(), Role::Player)));
+    assert_eq!(list_rooms(&mut lobby), vec![("a".to_string(), 1, 1)]);
+    // a room stays open while its spectator does, and a stray `Left` can't wrap the count around
+    futures::executor::block_on(lobby.handle_msg(LobbyMsg::Left("a".to_string(), Role::Player)));
+    futures::executor::block_on(lobby.handle_msg(LobbyMsg::Left("a".to_string(), Role::Player)));
+    assert_eq!(list_rooms(&mut lobby), vec![("a".to_string(), 0, 1)]);
+    futures::executor::block_on(lobby.handle_msg(LobbyMsg::Left("a".to_string(), Role::Spectator)));
+    assert!(list_rooms(&mut lobby).is_empty());
+}
+
+#[test]
+fn test_spectators() {
+    let (lobby_tx, _lobby_rx) = mpsc::unbounded_channel();
+    let mut state = ServerGameState::<SnakeGameState>::new(DEFAULT_ROOM.to_string(), SnakeGameConfig::default(), &ServerConfig::default(), lobby_tx);
+    let ((s2c_tx, _s2c_rx), (_c2s_tx, c2s_rx)) = (mpsc::unbounded_channel(), mpsc::unbounded_channel());
+    futures::executor::block_on(state.handle_msg(ServerInternalMsg::PlayerConnected(None, s2c_tx, c2s_rx)));
+    let mut spectators = vec![];
+    for _ in 0..3 {
+        let ((s2c_tx, mut s2c_rx), (c2s_tx, c2s_rx)) = (mpsc::unbounded_channel(), mpsc::unbounded_channel());
+        futures::executor::block_on(state.handle_msg(ServerInternalMsg::SpectatorConnected(s2c_tx, c2s_rx)));
+        assert!(matches!(s2c_rx.try_recv(), Ok(ServerToClient::Initialize { pid: None, session: None, .. })));
+        c2s_tx.send(ClientToServer::InputAtTick { tick: 0, input: SnakePlayerInput::SetBoost(true) }).unwrap();
+        spectators.push((s2c_rx, c2s_tx));
+    }
+    futures::executor::block_on(state.handle_msg(ServerInternalMsg::DoTick));
+    for (s2c_rx, _) in spectators.iter_mut() {
+        match s2c_rx.try_recv() {
+            Ok(ServerToClient::DoTick { tick: 0, inputs, .. }) => assert!(inputs.is_empty(), "{:?}", inputs),
+            x => panic!("expected the first tick, got {:?}", x),
+        }
+    }
+    // only the player has a snake, and only their snake counts towards the food on the board
+    let game = &state.game_state;
+    assert_eq!(game.player_segments.keys().cloned().collect::<Vec<_>>(), vec![PlayerId(0)]);
+    assert!(game.boosting.is_empty());
+    assert_eq!(game.num_foods, game.config.food_per_player + game.config.food_base);
 }
@@ -0,0 +1,222 @@
+//! Runs `SnakeGameState` headless, with bots standing in for players, printing every tick's events and a summary at the
+//! end. Handy for balance questions and for reproducing crashes without a server or a browser.
+
+#[macro_use] extern crate serde_derive;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use std::collections::BTreeMap;
+
+pub mod common;
+use common::*;
+use common::replay::Replay;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Bot {
+    /// Never turns, only respawns
+    Idle,
+    /// Turns a random way every few ticks, even into walls
+    Random,
+    /// Heads for the nearest food that isn't rotten without running into anything it can see
+    Greedy,
+}
+
+/// Inputs to make on top of the bots', by tick
+type Script = BTreeMap<u64, Vec<(PlayerId, SnakePlayerInput)>>;
+/// How many times each player died of each cause
+type Deaths = BTreeMap<PlayerId, BTreeMap<DeathCause, u64>>;
+
+fn main() {
+    let mut config = SnakeGameConfig::default();
+    let mut ticks = 1000;
+    let mut players = 4;
+    let mut bot = Bot::Greedy;
+    let mut script = BTreeMap::new();
+    let mut quiet = false;
+    let mut replay = None;
+    let mut args = std::env::args().skip(1);
+    let number = |arg: Option<String>, what: &str| -> u64 {
+        arg.and_then(|n| n.parse().ok()).unwrap_or_else(|| usage(&format!("{} needs a number", what)))
+    };
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--seed" => config.seed = number(args.next(), "--seed"),
+            "--size" => match parse_size(&args.next().unwrap_or_default()) {
+                Some((width, height)) => { config.width = width; config.height = height; },
+                None => usage("--size needs to be WIDTHxHEIGHT, at least 3x3"),
+            },
+            "--ticks" => ticks = number(args.next(), "--ticks"),
+            "--players" => players = number(args.next(), "--players") as usize,
+            "--bot" => bot = match args.next().as_deref() {
+                Some("idle") => Bot::Idle,
+                Some("random") => Bot::Random,
+                Some("greedy") => Bot::Greedy,
+                _ => usage("--bot needs to be idle, random or greedy"),
+            },
+            "--script" => script = load_script(&args.next().unwrap_or_else(|| usage("--script needs a path"))),
+            "--map" => {
+                let path = args.next().unwrap_or_else(|| usage("--map needs a path"));
+                let text = std::fs::read_to_string(&path).unwrap_or_else(|e| usage(&format!("couldn't read {}: {}", path, e)));
+                config.map = Some(Board::parse_map(&text).unwrap_or_else(|e| usage(&format!("{}: {}", path, e))));
+            },
+            "--replay" => replay = Some(args.next().unwrap_or_else(|| usage("--replay needs a path"))),
+            "--quiet" => quiet = true,
+            _ => usage(&format!("unrecognized argument {:?}", arg)),
+        }
+    }
+    if let Some(path) = replay {
+        return play_replay(&path, quiet);
+    }
+
+    let seed = config.seed;
+    let (state, deaths) = run(config, ticks, players, bot, &script, quiet).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    println!("{} ticks on a {}x{} board with seed {:#x}, final checksum {:016x}", state.tick, state.board.width, state.board.height, seed, state.checksum());
+    println!("{:>6} {:>6} {:>6} {:>6} {:>6}  deaths", "player", "food", "length", "peak", "kills");
+    for pid in (0..players).map(PlayerId) {
+        let score = state.scores.get(&pid).cloned().unwrap_or_default();
+        let causes = deaths.get(&pid).map(|causes| causes.iter().map(|(cause, n)| format!("{:?} {}", cause, n)).collect::<Vec<_>>().join(", ")).unwrap_or_default();
+        println!("{:>6} {:>6} {:>6} {:>6} {:>6}  {}", pid.0, score.food_eaten, score.length, score.peak_length, score.kills, causes);
+    }
+}
+
+fn usage(error: &str) -> ! {
+    eprintln!("{}", error);
+    eprintln!("usage: sim [--seed N] [--size WxH] [--ticks N] [--players N] [--bot idle|random|greedy] [--script PATH] [--map PATH] [--quiet]");
+    eprintln!("       sim --replay PATH [--quiet]");
+    std::process::exit(1);
+}
+
+/// `WIDTHxHEIGHT`, at least 3x3 so that there's an open tile inside the walls
+fn parse_size(size: &str) -> Option<(usize, usize)> {
+    let (width, height) = size.split_once('x')?;
+    let (width, height) = (width.parse().ok()?, height.parse().ok()?);
+    if width >= 3 && height >= 3 { Some((width, height)) } else { None }
+}
+
+/// Plays `ticks` ticks with every player a bot, returning the final state and how each player died along the way
+fn run(config: SnakeGameConfig, ticks: u64, players: usize, bot: Bot, script: &Script, quiet: bool) -> Result<(SnakeGameState, Deaths), String> {
+    let mut state = SnakeGameState::new(config);
+    let mut bot_rng = ChaCha20Rng::seed_from_u64(!state.config.seed);
+    let mut deaths = Deaths::new();
+    for pid in (0..players).map(PlayerId) {
+        state.player_joined(pid);
+    }
+    for _ in 0..ticks {
+        let mut inputs: BTreeMap<PlayerId, Vec<SnakePlayerInput>> = BTreeMap::new();
+        for pid in (0..players).map(PlayerId) {
+            if let Some(input) = bot_input(&state, pid, bot, &mut bot_rng) {
+                inputs.entry(pid).or_default().push(input);
+            }
+        }
+        for (pid, input) in script.get(&state.tick).into_iter().flatten() {
+            inputs.entry(*pid).or_default().push(*input);
+        }
+        let tick = state.tick;
+        let events = state.tick(&inputs);
+        for event in events.iter() {
+            if let SnakeGameEvent::PlayerDied(pid, cause, _) = event {
+                *deaths.entry(*pid).or_default().entry(*cause).or_default() += 1;
+            }
+            if !quiet {
+                println!("{}: {:?}", tick, event);
+            }
+        }
+        if let Err(e) = state.validate() {
+            return Err(format!("the world is inconsistent after tick {}: {:?}", tick, e));
+        }
+    }
+    Ok((state, deaths))
+}
+
+/// Reads inputs to make on top of the bots', one per line as `TICK PLAYER INPUT`, where `INPUT` is `up`, `down`, `left`,
+/// `right`, `respawn`, `boost` or `unboost`; blank lines and lines starting with `#` are skipped
+fn load_script(path: &str) -> Script {
+    let text = std::fs::read_to_string(path).unwrap_or_else(|e| usage(&format!("couldn't read {}: {}", path, e)));
+    let mut script = Script::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue
+        }
+        let error = || -> ! { usage(&format!("{}:{}: expected \"TICK PLAYER INPUT\", found {:?}", path, i + 1, line)) };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (tick, pid) = match &words[..] {
+            [tick, pid, _] => (tick.parse().unwrap_or_else(|_| error()), PlayerId(pid.parse().unwrap_or_else(|_| error()))),
+            _ => error(),
+        };
+        let input = match words[2] {
+            "up" => SnakePlayerInput::ChangeDirection(Direction::Up),
+            "down" => SnakePlayerInput::ChangeDirection(Direction::Down),
+            "left" => SnakePlayerInput::ChangeDirection(Direction::Left),
+            "right" => SnakePlayerInput::ChangeDirection(Direction::Right),
+            "respawn" => SnakePlayerInput::Respawn,
+            "boost" => SnakePlayerInput::SetBoost(true),
+            "unboost" => SnakePlayerInput::SetBoost(false),
+            _ => error(),
+        };
+        script.entry(tick).or_default().push((pid, input));
+    }
+    script
+}
+
+fn bot_input(state: &SnakeGameState, pid: PlayerId, bot: Bot, rng: &mut ChaCha20Rng) -> Option<SnakePlayerInput> {
+    use Direction::*;
+    let (head, heading) = match (state.player_segments.get(&pid).and_then(|segments| segments.back()), state.headings.get(&pid)) {
+        (Some(head), Some(heading)) => (*head, *heading),
+        _ => return if state.can_respawn(pid) { Some(SnakePlayerInput::Respawn) } else { None },
+    };
+    match bot {
+        Bot::Idle => None,
+        Bot::Random if rng.gen_range(0, 4) == 0 => Some(SnakePlayerInput::ChangeDirection(Direction::from_u32(rng.gen()))),
+        Bot::Random => None,
+        Bot::Greedy => {
+            let foods: Vec<Coord> = state.board.coords_where(|tile| matches!(tile, Tile::Food(FoodKind::Normal) | Tile::Food(FoodKind::Golden))).collect();
+            let safe = |dir: Direction| matches!(state.board.tile_or_wall(state.board.step(head, dir)), Tile::Empty | Tile::Food(_) | Tile::PowerUp(_));
+            let rotten = |dir: Direction| state.board.tile_or_wall(state.board.step(head, dir)) == Tile::Food(FoodKind::Rotten);
+            let distance = |dir: Direction| foods.iter().map(|food| state.board.distance(state.board.step(head, dir), *food)).min().unwrap_or(0);
+            // going straight wins ties, so that a bot with nowhere to go doesn't wiggle
+            let best = [heading, Up, Down, Left, Right].iter().cloned()
+                .filter(|dir| *dir != -heading && safe(*dir))
+                .min_by_key(|dir| (rotten(*dir), distance(*dir)))?;
+            if best != heading { Some(SnakePlayerInput::ChangeDirection(best)) } else { None }
+        },
+    }
+}
+
+fn play_replay(path: &str, quiet: bool) {
+    let file = std::fs::File::open(path).unwrap_or_else(|e| usage(&format!("couldn't read {}: {}", path, e)));
+    let replay = Replay::<SnakeGameState>::read_from(std::io::BufReader::new(file)).unwrap_or_else(|e| usage(&format!("{}: {}", path, e)));
+    let mut replayer = replay.play();
+    for result in &mut replayer {
+        match result {
+            Ok((tick, events)) => if !quiet {
+                for event in events.iter() {
+                    println!("{}: {:?}", tick, event);
+                }
+            },
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                std::process::exit(1);
+            },
+        }
+    }
+    println!("replayed {} ticks from {}, final checksum {:016x}", replayer.state.tick, path, replayer.state.checksum());
+}
+
+#[test]
+fn test_smallest_board() {
+    assert_eq!(parse_size("3x3"), Some((3, 3)));
+    assert_eq!(parse_size("2x9"), None);
+    assert_eq!(parse_size("3"), None);
+    for players in 0..3 {
+        for &bot in &[Bot::Idle, Bot::Random, Bot::Greedy] {
+            let config = SnakeGameConfig { width: 3, height: 3, ..SnakeGameConfig::default() };
+            let (state, _) = run(config, 100, players, bot, &BTreeMap::new(), true).unwrap();
+            assert_eq!(state.tick, 100);
+        }
+    }
+}